@@ -0,0 +1,64 @@
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use ch05::chip8::asm;
+use clap::{command, value_parser, Arg};
+
+fn main() -> ExitCode {
+    let matches = command!()
+        .about("Assembles a CHIP-8 program into a ROM image")
+        .arg(
+            Arg::new("source")
+                .value_name("SOURCE")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("Assembly source, as printed by chip8-dis"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("ROM_PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Where to write the ROM [default: SOURCE with the extension .ch8]"),
+        )
+        .get_matches();
+    let source_path = matches.get_one::<PathBuf>("source").unwrap();
+    let output_path = match matches.get_one::<PathBuf>("output") {
+        Some(path) => path.clone(),
+        None => source_path.with_extension("ch8"),
+    };
+
+    let source = match fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("chip8-asm: {}: {}", source_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Assembling `game.ch8` would otherwise replace the source with the ROM
+    if let (Ok(source_file), Ok(output_file)) = (
+        fs::canonicalize(source_path),
+        fs::canonicalize(&output_path),
+    ) {
+        if source_file == output_file {
+            eprintln!(
+                "chip8-asm: {}: the ROM would overwrite the source; choose another path with -o",
+                source_path.display()
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+    let rom = match asm::assemble(&source) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("chip8-asm: {}: {}", source_path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(err) = fs::write(&output_path, &rom) {
+        eprintln!("chip8-asm: {}: {}", output_path.display(), err);
+        return ExitCode::FAILURE;
+    }
+    println!("Wrote {} bytes to {}", rom.len(), output_path.display());
+    ExitCode::SUCCESS
+}
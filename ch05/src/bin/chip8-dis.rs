@@ -0,0 +1,33 @@
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use ch05::chip8::{disasm, RomError, MAX_ROM_LEN, PROGRAM_START};
+use clap::{command, value_parser, Arg};
+
+fn main() -> ExitCode {
+    let matches = command!()
+        .about("Prints a CHIP-8 program as annotated assembly")
+        .arg(
+            Arg::new("rom")
+                .value_name("ROM_PATH")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("The program image, loaded at address 0x200"),
+        )
+        .get_matches();
+    let path = matches.get_one::<PathBuf>("rom").unwrap();
+
+    let rom = match fs::read(path).map_err(RomError::from) {
+        Ok(rom) if rom.len() > MAX_ROM_LEN => Err(RomError::TooLarge { len: rom.len() }),
+        result => result,
+    };
+    match rom {
+        Ok(rom) => {
+            print!("{}", disasm::disassemble(&rom, PROGRAM_START as u16));
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("chip8-dis: {}: {}", path.display(), err);
+            ExitCode::FAILURE
+        }
+    }
+}
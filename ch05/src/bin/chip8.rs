@@ -1,133 +1,356 @@
-/// Simplified CHIP-8 CPU
-struct Cpu {
-    registers: [u8; 16],    // 16 registers
-    program_counter: usize, // program counter
-    memory: [u8; 0x1000],   // 4K RAM
-    stack: [u16; 16],
-    stack_pointer: usize,
+use std::{
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use ch05::chip8::{Cpu, Debugger, Fault, Framebuffer, Keypad, DISPLAY_WIDTH, TIMER_HZ};
+use clap::{command, value_parser, Arg, ArgAction};
+
+/// Instructions run per second unless `--speed` says otherwise
+const DEFAULT_SPEED: &str = "700";
+
+/// Frames a key typed in `--live` mode stays down. Terminals report key
+/// repeats but not releases, so this has to outlast the keyboard's delay
+/// before it starts repeating.
+const TYPED_KEY_FRAMES: u64 = 30;
+
+/// Ctrl-C, read as a byte since the terminal doesn't turn it into a signal
+/// in `--live` mode
+const INTERRUPT: u8 = 0x03;
+
+fn main() -> ExitCode {
+    let matches = command!()
+        .about("Runs a CHIP-8 program")
+        .arg(
+            Arg::new("rom")
+                .value_name("ROM_PATH")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("The program image, loaded at address 0x200"),
+        )
+        .arg(
+            Arg::new("max-cycles")
+                .long("max-cycles")
+                .value_name("N")
+                .value_parser(value_parser!(u64))
+                .help("Stop after N instructions if the program hasn't halted"),
+        )
+        .arg(
+            Arg::new("frames")
+                .long("frames")
+                .value_name("N")
+                .value_parser(value_parser!(u64))
+                .help("Stop after N frames, 60 per second, if the program hasn't halted"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("HZ")
+                .default_value(DEFAULT_SPEED)
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Instructions per second; the timers always run at 60 Hz"),
+        )
+        .arg(
+            Arg::new("hold")
+                .long("hold")
+                .value_name("KEYS")
+                .value_delimiter(',')
+                .value_parser(parse_key)
+                .help("Keys held down for the whole run, as hex digits: --hold 5,A"),
+        )
+        .arg(
+            Arg::new("live")
+                .long("live")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Run in real time, drawing the screen in the terminal every frame. \
+                     The keys 1234, qwer, asdf and zxcv are the keypad; Ctrl-C stops.",
+                ),
+        )
+        .arg(
+            Arg::new("debug")
+                .long("debug")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["live", "max-cycles", "frames"])
+                .help("Run the program under the debugger, reading commands from stdin"),
+        )
+        .get_matches();
+    let path = matches.get_one::<PathBuf>("rom").unwrap();
+    let max_cycles = matches.get_one::<u64>("max-cycles").copied();
+    let max_frames = matches.get_one::<u64>("frames").copied();
+    let speed = *matches.get_one::<u32>("speed").unwrap();
+    let live = matches.get_flag("live");
+    let debug = matches.get_flag("debug");
+
+    let mut cpu = match Cpu::with_rom_file(path) {
+        Ok(cpu) => cpu,
+        Err(err) => {
+            eprintln!("chip8: {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    for &key in matches.get_many::<u8>("hold").into_iter().flatten() {
+        cpu.keys.press(key);
+    }
+
+    let mut stdout = io::stdout().lock();
+    let result = if debug {
+        let mut debugger = Debugger::new(cpu, (speed / TIMER_HZ).max(1));
+        debugger
+            .repl(io::stdin().lock(), &mut stdout)
+            .map(|()| true)
+    } else if live {
+        Input::start(cpu.keys).and_then(|input| {
+            run(
+                &mut cpu,
+                speed,
+                max_cycles,
+                max_frames,
+                Some(input),
+                &mut stdout,
+            )
+        })
+    } else {
+        run(&mut cpu, speed, max_cycles, max_frames, None, &mut stdout)
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        // Stopped reading, as `head` does
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("chip8: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-impl Cpu {
-    fn read_opcode(&self) -> u16 {
-        let p = self.program_counter;
-        let op_byte1 = self.memory[p] as u16;
-        let op_byte2 = self.memory[p + 1] as u16;
-        (op_byte1 << 8) | op_byte2
+/// Runs the program until it halts or a limit is reached, then prints
+/// the state of the machine. With `live` input, it runs in real time.
+/// Returns `false` if it stopped on a fault.
+fn run(
+    cpu: &mut Cpu,
+    speed: u32,
+    max_cycles: Option<u64>,
+    max_frames: Option<u64>,
+    mut live: Option<Input>,
+    stdout: &mut impl Write,
+) -> io::Result<bool> {
+    let per_frame = (speed / TIMER_HZ).max(1);
+    let frame_time = Duration::from_secs(1) / TIMER_HZ;
+    if live.is_some() {
+        // Clear the terminal once; frames are drawn over each other
+        write!(stdout, "\x1b[2J")?;
     }
 
-    fn run(&mut self) {
-        loop {
-            let opcode = self.read_opcode();
-            // Set the next instruction
-            self.program_counter += 2;
-
-            let c = ((opcode & 0xF000) >> 12) as u8;
-            let x = ((opcode & 0x0F00) >> 8) as u8;
-            let y = ((opcode & 0x00F0) >> 4) as u8;
-            let d = (opcode & 0x000F) as u8;
-            let nnn = opcode & 0xFFF;
-            match (c, x, y, d) {
-                (0, 0, 0, 0) => {
-                    return; // Termination
+    let mut cycles = 0;
+    let mut frames = 0;
+    let mut next_frame = Instant::now();
+    let mut was_sound_on = false;
+    let end = 'run: loop {
+        if max_frames == Some(frames) {
+            break End::Stopped;
+        }
+        if let Some(input) = &mut live {
+            if !input.update(&mut cpu.keys, frames) {
+                break End::Stopped;
+            }
+        }
+        for _ in 0..per_frame {
+            if max_cycles == Some(cycles) {
+                break 'run End::Stopped;
+            }
+            match cpu.try_step() {
+                Ok(true) => cycles += 1,
+                Ok(false) => {
+                    cycles += 1;
+                    break 'run End::Halted;
                 }
-                (0, 0, 0xE, 0xE) => self.ret(),
-                (0x2, _, _, _) => self.call(nnn),
-                (0x8, _, _, 0x4) => self.add_xy(x, y),
-                _ => todo!("opcode {:04x}", opcode),
+                Err(fault) => break 'run End::Fault(fault),
             }
         }
-    }
+        cpu.tick_timers();
+        frames += 1;
 
-    fn add_xy(&mut self, x: u8, y: u8) {
-        let arg1 = self.registers[x as usize];
-        let arg2 = self.registers[y as usize];
-        let (val, overflow) = arg1.overflowing_add(arg2);
-        self.registers[x as usize] = val;
-        if overflow {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
+        if live.is_some() {
+            write!(stdout, "\x1b[H{}", framed(&cpu.display))?;
+            if cpu.is_sound_on() && !was_sound_on {
+                write!(stdout, "\x07")?;
+            }
+            was_sound_on = cpu.is_sound_on();
+            stdout.flush()?;
+
+            next_frame += frame_time;
+            if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
         }
+    };
+
+    if live.is_some() {
+        // Give the terminal back before printing the final state
+        drop(live);
+        write!(stdout, "\x1b[2J\x1b[H")?;
+    }
+    match end {
+        End::Halted => writeln!(stdout, "Halted after {} instructions", cycles)?,
+        End::Stopped => writeln!(stdout, "Stopped after {} instructions", cycles)?,
+        End::Fault(fault) => writeln!(stdout, "{} after {} instructions", fault, cycles)?,
     }
+    writeln!(
+        stdout,
+        "PC: {:#05x}  I: {:#05x}  DT: {}  ST: {}  frames: {}",
+        cpu.program_counter, cpu.index_register, cpu.delay_timer, cpu.sound_timer, frames
+    )?;
+    for (r, value) in cpu.registers.iter().enumerate() {
+        write!(
+            stdout,
+            "V{:X}: {:#04x}{}",
+            r,
+            value,
+            if r % 8 == 7 { "\n" } else { "  " }
+        )?;
+    }
+    write!(stdout, "{}", framed(&cpu.display))?;
+    Ok(!matches!(end, End::Fault(_)))
+}
 
-    fn call(&mut self, addr: u16) {
-        let sp = self.stack_pointer;
-        let stack = &mut self.stack;
+/// Keys typed at the terminal, for `--live` mode
+struct Input {
+    typed: Receiver<u8>,
 
-        if sp > stack.len() {
-            panic!("Stack overflow!");
-        }
+    /// Keys given with `--hold`, which are never released
+    held: Keypad,
 
-        // Save the program counter. As in run(), it is current instruction + 2
-        stack[sp] = self.program_counter as u16;
-        self.stack_pointer += 1; // The slot in the stack is taken by program counter
+    /// Frame at which each key typed is released
+    release_at: [Option<u64>; 16],
 
-        // Go to the specified address
-        self.program_counter = addr as usize;
+    _raw_mode: RawMode,
+}
+
+impl Input {
+    /// Starts reading the terminal a key at a time, on a thread of its own
+    fn start(held: Keypad) -> io::Result<Input> {
+        let raw_mode = RawMode::enable()?;
+        let (sender, typed) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => {}
+                    _ => return,
+                }
+            }
+        });
+        Ok(Input {
+            typed,
+            held,
+            release_at: [None; 16],
+            _raw_mode: raw_mode,
+        })
     }
 
-    fn ret(&mut self) {
-        if self.stack_pointer == 0 {
-            panic!("Stack underflow");
+    /// Presses the keys typed since the last frame and releases those that
+    /// haven't been typed for a while.
+    /// Returns `false` once Ctrl-C has been typed.
+    fn update(&mut self, keys: &mut Keypad, frame: u64) -> bool {
+        for byte in self.typed.try_iter() {
+            if byte == INTERRUPT {
+                return false;
+            }
+            if let Some(key) = Keypad::key_for_char(byte as char) {
+                keys.press(key);
+                self.release_at[key as usize] = Some(frame + TYPED_KEY_FRAMES);
+            }
         }
-
-        // Find the position of the caller and unwind the stack
-        self.stack_pointer -= 1;
-        let call_addr = self.stack[self.stack_pointer];
-
-        // Jump to it
-        self.program_counter = call_addr as usize;
+        for key in 0..16 {
+            if self.release_at[key as usize].is_some_and(|release_at| release_at <= frame) {
+                self.release_at[key as usize] = None;
+                if !self.held.is_down(key) {
+                    keys.release(key);
+                }
+            }
+        }
+        true
     }
 }
 
-fn main() {
-    /////////////////////////////////////////////
-    // Initialize the machine
-    /////////////////////////////////////////////
-    let mut cpu = Cpu {
-        registers: [0; 16],
-        memory: [0; 4096],
-        program_counter: 0,
-        stack: [0; 16],
-        stack_pointer: 0,
-    };
-
-    cpu.registers[0] = 5;
-    cpu.registers[1] = 10;
+/// Puts the terminal in non-canonical mode without echo, so that keys are
+/// read as they are typed, and restores its settings when dropped.
+/// Does nothing if stdin isn't a terminal.
+#[cfg(not(windows))]
+struct RawMode(Option<libc::termios>);
 
-    let mem = &mut cpu.memory;
+#[cfg(not(windows))]
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+            return Ok(RawMode(None));
+        }
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
 
-    /////////////////////////////////////////////
-    // Program the machine
-    /////////////////////////////////////////////
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode(Some(saved)))
+    }
+}
 
-    // Define a function
-    let add_twice: [u8; 6] = [
-        0x80, 0x14, // add reg[0] reg[1]
-        0x80, 0x14, // add reg[0] reg[1]
-        0x00, 0xEE, // return
-    ];
+#[cfg(not(windows))]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+}
 
-    // Load the function into memory, at ADDR 0x100
-    mem[0x100..0x106].copy_from_slice(&add_twice);
+/// The console is left as it is on Windows: keys are only read once Enter
+/// is pressed.
+#[cfg(windows)]
+struct RawMode;
 
-    // Main program
-    // Call the function, opcode = 2, addr = 100
-    mem[0x000] = 0x21;
-    mem[0x001] = 0x00;
+#[cfg(windows)]
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        Ok(RawMode)
+    }
+}
 
-    // Call the function again
-    mem[0x002] = 0x21;
-    mem[0x003] = 0x00;
+/// How a run ended
+enum End {
+    Halted,
 
-    // HALT
-    mem[0x004] = 0x00;
-    mem[0x005] = 0x00;
+    /// Reached `--max-cycles` or `--frames`
+    Stopped,
 
-    println!("{:?}", &mem[0x100..0x106]);
+    Fault(Fault),
+}
 
-    cpu.run();
+/// The screen in a box, so that blank rows and columns show
+fn framed(display: &Framebuffer) -> String {
+    let border = "─".repeat(DISPLAY_WIDTH);
+    let mut out = format!("┌{}┐\n", border);
+    for line in display.to_string().lines() {
+        out += &format!("│{}│\n", line);
+    }
+    out += &format!("└{}┘\n", border);
+    out
+}
 
-    assert_eq!(cpu.registers[0], 45);
-    println!("5 + (10 * 2) + (10 * 2) = {}", cpu.registers[0]);
+fn parse_key(s: &str) -> Result<u8, String> {
+    match u8::from_str_radix(s.trim(), 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("not a key from 0 to F: {}", s)),
+    }
 }
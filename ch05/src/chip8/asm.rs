@@ -0,0 +1,249 @@
+//! Turning assembly into a ROM.
+//!
+//! One statement per line, in the syntax `disasm::disassemble` writes:
+//!
+//! ```text
+//! ; Comments run from a semicolon to the end of the line
+//! start:  LD V0, 0x05     ; a label names the address of what follows it
+//!         CALL double
+//!         HALT
+//! double: ADD V0, V0
+//!         RET
+//! sprite: DB 0xF0, 0x90   ; raw bytes
+//!         DW 0x1234       ; raw big-endian words
+//! ```
+//!
+//! Mnemonics, registers and labels are case-insensitive. Numbers are decimal,
+//! `0x` hexadecimal or `0b` binary; an address may also be a label.
+//! The program is assembled to run from `PROGRAM_START`.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use super::instruction::{OpcodeSpec, Operand, OPCODES};
+use super::rom::PROGRAM_START;
+
+/// Operands written the same way every time, which can't be used as labels
+const FIXED_OPERANDS: [&str; 7] = ["I", "[I]", "DT", "ST", "K", "F", "B"];
+
+/// An error in the source, with the number of the line it is on, from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A line with an instruction or data on it
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// A parsed operand, before labels are resolved
+enum Arg<'a> {
+    Register(u8),
+
+    /// One of `FIXED_OPERANDS`, in upper case
+    Fixed(String),
+
+    /// A number or a label
+    Value(&'a str),
+}
+
+/// Assembles `source` into a ROM image
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // First pass: find where every statement goes, to know what labels stand for
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+    let mut addr = PROGRAM_START;
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let error = |message: String| AsmError { line, message };
+
+        let mut rest = text.split(';').next().unwrap().trim();
+        while let Some((label, after)) = split_label(rest) {
+            if !is_valid_label(label) {
+                return Err(error(format!("invalid label name: {}", label)));
+            }
+            if labels.insert(label.to_ascii_uppercase(), addr).is_some() {
+                return Err(error(format!("label defined twice: {}", label)));
+            }
+            rest = after.trim_start();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match rest.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(str::trim).collect()),
+            None => (rest, Vec::new()),
+        };
+        let statement = Statement {
+            line,
+            mnemonic: mnemonic.to_ascii_uppercase(),
+            operands,
+        };
+        if statement.operands.iter().any(|operand| operand.is_empty()) {
+            return Err(error("missing operand".to_string()));
+        }
+        addr += match statement.mnemonic.as_str() {
+            "DB" => statement.operands.len(),
+            "DW" => statement.operands.len() * 2,
+            _ => 2,
+        };
+        if addr > 0x1000 {
+            return Err(error("program doesn't fit in memory".to_string()));
+        }
+        statements.push(statement);
+    }
+
+    // Second pass: encode
+    let mut rom = Vec::with_capacity(addr - PROGRAM_START);
+    for statement in &statements {
+        let error = |message: String| AsmError {
+            line: statement.line,
+            message,
+        };
+        let value = |operand: &str, max: u16| -> Result<u16, AsmError> {
+            let value = match parse_number(operand) {
+                Some(value) => value,
+                None if is_valid_label(operand) => {
+                    match labels.get(&operand.to_ascii_uppercase()) {
+                        Some(&addr) => addr as u16,
+                        None => return Err(error(format!("undefined label: {}", operand))),
+                    }
+                }
+                None => return Err(error(format!("invalid number: {}", operand))),
+            };
+            if value > max {
+                return Err(error(format!(
+                    "{} is out of range, the maximum is {:#X}",
+                    operand, max
+                )));
+            }
+            Ok(value)
+        };
+
+        match statement.mnemonic.as_str() {
+            "DB" | "DW" if statement.operands.is_empty() => {
+                return Err(error(format!(
+                    "{} needs at least one value",
+                    statement.mnemonic
+                )));
+            }
+            "DB" => {
+                for operand in &statement.operands {
+                    rom.push(value(operand, 0xFF)? as u8);
+                }
+            }
+            "DW" => {
+                for operand in &statement.operands {
+                    rom.extend_from_slice(&value(operand, 0xFFFF)?.to_be_bytes());
+                }
+            }
+            mnemonic => {
+                let args: Vec<Arg> = statement.operands.iter().map(|s| parse_arg(s)).collect();
+                let spec = find_spec(mnemonic, &args).map_err(error)?;
+                let mut values = Vec::with_capacity(args.len());
+                for (operand, arg) in spec.operands.iter().zip(&args) {
+                    values.push(match (operand, arg) {
+                        (_, Arg::Register(r)) => *r as u16,
+                        (_, Arg::Fixed(_)) => 0,
+                        (Operand::Nibble, Arg::Value(s)) => value(s, 0xF)?,
+                        (Operand::Byte, Arg::Value(s)) => value(s, 0xFF)?,
+                        (_, Arg::Value(s)) => value(s, 0xFFF)?,
+                    });
+                }
+                rom.extend_from_slice(&spec.encode(&values).to_be_bytes());
+            }
+        }
+    }
+    Ok(rom)
+}
+
+/// Splits `name:` off the start of a line
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    // A colon further along, after an instruction, isn't a label's
+    if label.contains(char::is_whitespace) || label.is_empty() {
+        return None;
+    }
+    Some((label, rest))
+}
+
+fn is_valid_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_well = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_');
+    starts_well
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_register(name).is_none()
+        && !FIXED_OPERANDS.contains(&name.to_ascii_uppercase().as_str())
+}
+
+fn parse_register(s: &str) -> Option<u8> {
+    match s.as_bytes() {
+        [b'V' | b'v', digit] => (*digit as char).to_digit(16).map(|r| r as u8),
+        _ => None,
+    }
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn parse_arg(s: &str) -> Arg<'_> {
+    let upper = s.to_ascii_uppercase();
+    if let Some(r) = parse_register(s) {
+        Arg::Register(r)
+    } else if FIXED_OPERANDS.contains(&upper.as_str()) {
+        Arg::Fixed(upper)
+    } else {
+        Arg::Value(s)
+    }
+}
+
+/// The table entry for a mnemonic with operands of these kinds
+fn find_spec(mnemonic: &str, args: &[Arg]) -> Result<&'static OpcodeSpec, String> {
+    let mut known = false;
+    for spec in OPCODES.iter().filter(|spec| spec.mnemonic == mnemonic) {
+        known = true;
+        if spec.operands.len() != args.len() {
+            continue;
+        }
+        let fits = spec
+            .operands
+            .iter()
+            .zip(args)
+            .all(|(operand, arg)| match (operand, arg) {
+                (Operand::Vx | Operand::Vy, Arg::Register(_)) => true,
+                (Operand::Byte | Operand::Nibble | Operand::Addr, Arg::Value(_)) => true,
+                (Operand::Fixed("V0"), Arg::Register(0)) => true,
+                (Operand::Fixed(name), Arg::Fixed(fixed)) => name == fixed,
+                _ => false,
+            });
+        if fits {
+            return Ok(spec);
+        }
+    }
+
+    if known {
+        Err(format!("invalid operands for {}", mnemonic))
+    } else {
+        Err(format!("unknown instruction: {}", mnemonic))
+    }
+}
@@ -0,0 +1,683 @@
+//! A step debugger, driven by commands typed at a prompt.
+//!
+//! `Debugger::repl` reads commands from any `BufRead` and writes to any
+//! `Write`, so it works the same in a terminal, over SSH or fed by a script.
+//! Type `help` at the prompt for the commands.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+};
+
+use super::{disasm, Cpu, Fault, Instruction, Op};
+
+/// Number of instructions run that `trace` remembers
+pub const TRACE_LEN: usize = 256;
+
+/// `continue`, and `step` with a larger count, give up after this many
+/// instructions without a reason to stop, so that a program looping forever
+/// doesn't lock the prompt out
+pub const CONTINUE_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+Addresses are hexadecimal, counts decimal. An empty line repeats the last command.
+  step [N]             s    run N instructions (1), stopping early at breakpoints
+  continue             c    run until a breakpoint, watchpoint, halt or fault
+  break [ADDR]         b    set a breakpoint at ADDR, or list them
+  delete ADDR          d    remove the breakpoint at ADDR
+  watch [ADDR [LEN]]   w    stop when LEN bytes (1) from ADDR are written, or list
+  unwatch ADDR [LEN]        stop watching LEN bytes (1) from ADDR
+  regs                 r    show the registers and timers
+  stack                     show the return addresses on the stack
+  mem ADDR [LEN]       x    show LEN bytes (64) of memory from ADDR
+  dis [ADDR] [N]       l    disassemble N instructions (10) from ADDR (PC)
+  screen                    show the screen
+  trace [N]            t    show the last N instructions run (20)
+  log FILE | off            log every instruction run to FILE, or stop logging
+  press KEY / release KEY   hold a key of the keypad down, or let go of it
+  help                 h    show this
+  quit                 q    leave the debugger";
+
+/// An instruction that was run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Number of instructions run before it
+    pub cycle: u64,
+    pub addr: usize,
+    pub opcode: u16,
+}
+
+/// Why the machine stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// Ran the number of instructions asked for
+    Done,
+
+    /// Reached the instruction at a breakpoint, without running it
+    Breakpoint(usize),
+
+    /// The instruction at `pc` wrote to the watched address `addr`
+    Watchpoint {
+        addr: usize,
+        pc: usize,
+        old: u8,
+        new: u8,
+    },
+
+    /// Ran the halt opcode `0000`
+    Halted,
+
+    /// Hit an instruction that can't be run, leaving the machine as it was
+    Fault(Fault),
+
+    /// `continue`, or a `step` of more, ran `CONTINUE_LIMIT` instructions
+    Limit,
+}
+
+/// A machine being debugged
+pub struct Debugger {
+    pub cpu: Cpu,
+
+    /// The timers tick every this many instructions, as in `Cpu::run_frame`
+    instructions_per_frame: u32,
+
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+
+    /// The last `TRACE_LEN` instructions run
+    trace: VecDeque<TraceEntry>,
+
+    /// Where every instruction run is written, besides `trace`
+    log: Option<BufWriter<File>>,
+
+    /// Error that stopped the log, not reported yet
+    log_error: Option<io::Error>,
+
+    /// Instructions run so far
+    cycles: u64,
+
+    halted: bool,
+
+    /// Run again when an empty line is entered
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu, instructions_per_frame: u32) -> Self {
+        Debugger {
+            cpu,
+            instructions_per_frame: instructions_per_frame.max(1),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            trace: VecDeque::with_capacity(TRACE_LEN),
+            log: None,
+            log_error: None,
+            cycles: 0,
+            halted: false,
+            last_command: String::new(),
+        }
+    }
+
+    /// Number of instructions run so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns `false` if there already was a breakpoint at `addr`
+    pub fn add_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Returns `false` if there was no breakpoint at `addr`
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.insert(addr & 0xFFF)
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&(addr & 0xFFF))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// The last instructions run, oldest first
+    pub fn trace(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.trace.iter()
+    }
+
+    /// Starts writing every instruction run to `file`, or stops with `None`
+    pub fn log_to(&mut self, file: Option<File>) -> io::Result<()> {
+        if let Some(mut log) = self.log.take() {
+            log.flush()?;
+        }
+        self.log = file.map(BufWriter::new);
+        Ok(())
+    }
+
+    /// Runs one instruction
+    pub fn step(&mut self) -> Stop {
+        if self.halted {
+            return Stop::Halted;
+        }
+
+        let pc = self.cpu.program_counter;
+        // Read before running it, in case it overwrites itself
+        let opcode = if pc + 1 < self.cpu.memory.len() {
+            self.cpu.read_opcode()
+        } else {
+            0
+        };
+        let watched: Vec<(usize, u8)> = self
+            .pending_writes()
+            .into_iter()
+            .filter(|addr| self.watchpoints.contains(addr))
+            .map(|addr| (addr, self.cpu.memory[addr]))
+            .collect();
+
+        let running = match self.cpu.try_step() {
+            Ok(running) => running,
+            Err(fault) => return Stop::Fault(fault),
+        };
+        self.record(TraceEntry {
+            cycle: self.cycles,
+            addr: pc,
+            opcode,
+        });
+        self.cycles += 1;
+        if self
+            .cycles
+            .is_multiple_of(self.instructions_per_frame as u64)
+        {
+            self.cpu.tick_timers();
+        }
+
+        if !running {
+            self.halted = true;
+            return Stop::Halted;
+        }
+        match watched.first() {
+            Some(&(addr, old)) => Stop::Watchpoint {
+                addr,
+                pc,
+                old,
+                new: self.cpu.memory[addr],
+            },
+            None => Stop::Done,
+        }
+    }
+
+    /// Runs up to `count` instructions, stopping early at a breakpoint,
+    /// a watchpoint, a halt or a fault.
+    /// A breakpoint at the first instruction doesn't stop it, so that
+    /// running again after stopping at one moves on.
+    pub fn run(&mut self, count: u64) -> Stop {
+        for i in 0..count {
+            let pc = self.cpu.program_counter;
+            if i > 0 && self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+            match self.step() {
+                Stop::Done => {}
+                stop => return stop,
+            }
+        }
+        Stop::Done
+    }
+
+    /// Addresses the next instruction will write to, if any are watched
+    fn pending_writes(&self) -> Vec<usize> {
+        let pc = self.cpu.program_counter;
+        if self.watchpoints.is_empty() || pc + 1 >= self.cpu.memory.len() {
+            return Vec::new();
+        }
+        let len = match Instruction::decode(self.cpu.read_opcode()) {
+            Some(instruction) if instruction.op() == Op::StoreBcd => 3,
+            Some(instruction) if instruction.op() == Op::StoreRegisters => {
+                instruction.x() as usize + 1
+            }
+            _ => 0,
+        };
+        let i = self.cpu.index_register as usize;
+        (0..len).map(|offset| (i + offset) & 0xFFF).collect()
+    }
+
+    fn record(&mut self, entry: TraceEntry) {
+        if self.trace.len() == TRACE_LEN {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(entry);
+
+        if let Some(log) = &mut self.log {
+            if let Err(err) = writeln!(log, "{}", trace_line(&entry)) {
+                self.log = None;
+                self.log_error = Some(err);
+            }
+        }
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input
+    pub fn repl(&mut self, mut input: impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        loop {
+            write!(output, "(chip8) ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                break;
+            }
+
+            let line = line.trim();
+            let command = if line.is_empty() {
+                self.last_command.clone()
+            } else {
+                line.to_string()
+            };
+            if command.is_empty() {
+                continue;
+            }
+            self.last_command = command.clone();
+            if !self.execute(&command, output)? {
+                break;
+            }
+        }
+        self.log_to(None)
+    }
+
+    /// Runs a command typed at the prompt.
+    /// Returns `false` if it was `quit`.
+    pub fn execute(&mut self, command: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        let result = match name {
+            "step" | "s" => self.cmd_step(&args, out),
+            "continue" | "c" => self.cmd_continue(&args, out),
+            "break" | "b" => self.cmd_break(&args, out),
+            "delete" | "d" => self.cmd_delete(&args, out),
+            "watch" | "w" => self.cmd_watch(&args, out),
+            "unwatch" => self.cmd_unwatch(&args, out),
+            "regs" | "r" => self.cmd_regs(out),
+            "stack" => self.cmd_stack(out),
+            "mem" | "x" => self.cmd_mem(&args, out),
+            "dis" | "l" => self.cmd_dis(&args, out),
+            "screen" => write!(out, "{}", self.cpu.display).map_err(CommandError::from),
+            "trace" | "t" => self.cmd_trace(&args, out),
+            "log" => self.cmd_log(&args, out),
+            "press" | "release" => self.cmd_key(name == "press", &args, out),
+            "help" | "h" | "?" => writeln!(out, "{}", HELP).map_err(CommandError::from),
+            "quit" | "q" => return Ok(false),
+            _ => Err(CommandError::Usage(format!(
+                "Unknown command: {}. Type help for the list.",
+                name
+            ))),
+        };
+        match result {
+            Ok(()) => {}
+            Err(CommandError::Usage(message)) => writeln!(out, "{}", message)?,
+            Err(CommandError::Io(err)) => return Err(err),
+        }
+
+        if let Some(err) = self.log_error.take() {
+            writeln!(out, "Cannot write the log, stopped logging: {}", err)?;
+        }
+        Ok(true)
+    }
+
+    fn cmd_step(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let count = match args {
+            [] => 1,
+            [count] => parse_count(count)?,
+            _ => return Err(usage("step [N]")),
+        };
+        if count <= CONTINUE_LIMIT {
+            let stop = self.run(count);
+            return self.report(stop, out);
+        }
+        let stop = match self.run(CONTINUE_LIMIT) {
+            Stop::Done => Stop::Limit,
+            stop => stop,
+        };
+        self.report(stop, out)
+    }
+
+    fn cmd_continue(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        if !args.is_empty() {
+            return Err(usage("continue"));
+        }
+        let stop = match self.run(CONTINUE_LIMIT) {
+            Stop::Done => Stop::Limit,
+            stop => stop,
+        };
+        self.report(stop, out)
+    }
+
+    /// Says why the machine stopped and where
+    fn report(&self, stop: Stop, out: &mut impl Write) -> Result<(), CommandError> {
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint(addr) => writeln!(out, "Breakpoint at {:#05x}", addr)?,
+            Stop::Watchpoint { addr, pc, old, new } => writeln!(
+                out,
+                "Watchpoint: {:#05x} written by the instruction at {:#05x}: {:#04x} -> {:#04x}",
+                addr, pc, old, new
+            )?,
+            Stop::Halted => {
+                writeln!(out, "Halted after {} instructions", self.cycles)?;
+                return Ok(());
+            }
+            Stop::Fault(fault) => writeln!(out, "Fault: {}", fault)?,
+            Stop::Limit => writeln!(
+                out,
+                "No breakpoint after {} instructions, stopped",
+                CONTINUE_LIMIT
+            )?,
+        }
+        writeln!(out, "{}", self.location())?;
+        Ok(())
+    }
+
+    fn cmd_break(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        match args {
+            [] => list(out, "breakpoints", self.breakpoints()),
+            [addr] => {
+                let addr = parse_addr(addr)?;
+                if self.add_breakpoint(addr) {
+                    writeln!(out, "Breakpoint at {:#05x}", addr)?;
+                } else {
+                    writeln!(out, "There already is a breakpoint at {:#05x}", addr)?;
+                }
+                Ok(())
+            }
+            _ => Err(usage("break [ADDR]")),
+        }
+    }
+
+    fn cmd_delete(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let addr = match args {
+            [addr] => parse_addr(addr)?,
+            _ => return Err(usage("delete ADDR")),
+        };
+        if self.remove_breakpoint(addr) {
+            writeln!(out, "Deleted the breakpoint at {:#05x}", addr)?;
+        } else {
+            writeln!(out, "No breakpoint at {:#05x}", addr)?;
+        }
+        Ok(())
+    }
+
+    fn cmd_watch(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        if args.is_empty() {
+            return list(out, "watchpoints", self.watchpoints());
+        }
+        let (addr, len) = parse_range(args, 1, "watch [ADDR [LEN]]")?;
+        for offset in 0..len {
+            self.add_watchpoint(addr + offset);
+        }
+        writeln!(out, "Watching {}", describe_range(addr, len))?;
+        Ok(())
+    }
+
+    fn cmd_unwatch(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let (addr, len) = parse_range(args, 1, "unwatch ADDR [LEN]")?;
+        for offset in 0..len {
+            self.remove_watchpoint(addr + offset);
+        }
+        writeln!(out, "No longer watching {}", describe_range(addr, len))?;
+        Ok(())
+    }
+
+    fn cmd_regs(&self, out: &mut impl Write) -> Result<(), CommandError> {
+        let cpu = &self.cpu;
+        writeln!(
+            out,
+            "PC: {:#05x}  I: {:#05x}  SP: {}  DT: {}  ST: {}  cycles: {}",
+            cpu.program_counter,
+            cpu.index_register,
+            cpu.stack_pointer,
+            cpu.delay_timer,
+            cpu.sound_timer,
+            self.cycles
+        )?;
+        for (r, value) in cpu.registers.iter().enumerate() {
+            let end = if r % 8 == 7 { "\n" } else { "  " };
+            write!(out, "V{:X}: {:#04x}{}", r, value, end)?;
+        }
+        Ok(())
+    }
+
+    fn cmd_stack(&self, out: &mut impl Write) -> Result<(), CommandError> {
+        let depth = self.cpu.stack_pointer;
+        if depth == 0 {
+            writeln!(out, "The stack is empty")?;
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "{} of {} slots used, innermost first:",
+            depth,
+            self.cpu.stack.len()
+        )?;
+        for slot in (0..depth).rev() {
+            writeln!(
+                out,
+                "  {:>2}: return to {:#05x}",
+                slot, self.cpu.stack[slot]
+            )?;
+        }
+        Ok(())
+    }
+
+    fn cmd_mem(&self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let (addr, len) = parse_range(args, 64, "mem ADDR [LEN]")?;
+        let memory = &self.cpu.memory;
+        let end = (addr + len).min(memory.len());
+        for row in (addr..end).step_by(16) {
+            let bytes = &memory[row..(row + 16).min(end)];
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            writeln!(out, "{:#05x}: {}", row, hex.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn cmd_dis(&self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let pc = self.cpu.program_counter;
+        let (addr, count) = match args {
+            [] => (pc, 10),
+            [addr] => (parse_addr(addr)?, 10),
+            [addr, count] => (parse_addr(addr)?, parse_count(count)? as usize),
+            _ => return Err(usage("dis [ADDR] [N]")),
+        };
+        for i in 0..count {
+            let at = addr + i * 2;
+            if at + 1 >= self.cpu.memory.len() {
+                break;
+            }
+            let marker = if at == pc { "=> " } else { "   " };
+            write!(out, "{}{}", marker, self.listing(at))?;
+        }
+        Ok(())
+    }
+
+    fn cmd_trace(&self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        let count = match args {
+            [] => 20,
+            [count] => parse_count(count)? as usize,
+            _ => return Err(usage("trace [N]")),
+        };
+        if self.trace.is_empty() {
+            writeln!(out, "No instructions run yet")?;
+        }
+        let skip = self.trace.len().saturating_sub(count);
+        for entry in self.trace.iter().skip(skip) {
+            writeln!(out, "{}", trace_line(entry))?;
+        }
+        Ok(())
+    }
+
+    fn cmd_log(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), CommandError> {
+        match args {
+            ["off"] => {
+                self.log_to(None)?;
+                writeln!(out, "Stopped logging")?;
+            }
+            [path] => match File::create(path) {
+                Ok(file) => {
+                    self.log_to(Some(file))?;
+                    writeln!(out, "Logging every instruction run to {}", path)?;
+                }
+                Err(err) => writeln!(out, "Cannot create {}: {}", path, err)?,
+            },
+            _ => return Err(usage("log FILE | off")),
+        }
+        Ok(())
+    }
+
+    fn cmd_key(
+        &mut self,
+        press: bool,
+        args: &[&str],
+        out: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        let key = match args {
+            [key] => match u8::from_str_radix(key, 16) {
+                Ok(key) if key < 16 => key,
+                _ => {
+                    return Err(CommandError::Usage(format!(
+                        "Not a key from 0 to F: {}",
+                        key
+                    )))
+                }
+            },
+            _ => return Err(usage(if press { "press KEY" } else { "release KEY" })),
+        };
+        if press {
+            self.cpu.keys.press(key);
+            writeln!(out, "Key {:X} is down", key)?;
+        } else {
+            self.cpu.keys.release(key);
+            writeln!(out, "Key {:X} is up", key)?;
+        }
+        Ok(())
+    }
+
+    /// The next instruction to run
+    fn location(&self) -> String {
+        let pc = self.cpu.program_counter;
+        if pc + 1 >= self.cpu.memory.len() {
+            return format!("=> {:#05x}: past the end of memory", pc);
+        }
+        format!("=> {}", self.listing(pc).trim_end())
+    }
+
+    /// A line of disassembly for the instruction at `addr`
+    fn listing(&self, addr: usize) -> String {
+        disasm::disassemble(&self.cpu.memory[addr..addr + 2], addr as u16)
+    }
+}
+
+/// Errors of a command: either a mistake in how it was typed, reported at
+/// the prompt, or a failure to write the output, which ends the REPL
+enum CommandError {
+    Usage(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Io(err)
+    }
+}
+
+fn usage(syntax: &str) -> CommandError {
+    CommandError::Usage(format!("Usage: {}", syntax))
+}
+
+fn list(
+    out: &mut impl Write,
+    what: &str,
+    addrs: impl Iterator<Item = usize>,
+) -> Result<(), CommandError> {
+    let addrs: Vec<String> = addrs.map(|addr| format!("{:#05x}", addr)).collect();
+    if addrs.is_empty() {
+        writeln!(out, "No {}", what)?;
+    } else {
+        writeln!(out, "{}: {}", capitalize(what), addrs.join(" "))?;
+    }
+    Ok(())
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+fn describe_range(addr: usize, len: usize) -> String {
+    if len == 1 {
+        format!("{:#05x}", addr)
+    } else {
+        format!("{:#05x} to {:#05x}", addr, addr + len - 1)
+    }
+}
+
+fn trace_line(entry: &TraceEntry) -> String {
+    let listing = match Instruction::decode(entry.opcode) {
+        Some(instruction) => instruction.to_string(),
+        None => format!("DW 0x{:04X}", entry.opcode),
+    };
+    format!(
+        "{:>8}  {:#05x}  {:04X}  {}",
+        entry.cycle, entry.addr, entry.opcode, listing
+    )
+}
+
+/// An address in memory, in hexadecimal with or without `0x`
+fn parse_addr(s: &str) -> Result<usize, CommandError> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    match usize::from_str_radix(digits, 16) {
+        Ok(addr) if addr < 0x1000 => Ok(addr),
+        _ => Err(CommandError::Usage(format!(
+            "Not an address from 0 to fff: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_count(s: &str) -> Result<u64, CommandError> {
+    s.parse()
+        .map_err(|_| CommandError::Usage(format!("Not a count: {}", s)))
+}
+
+/// `ADDR [LEN]`, clipped to the end of memory. `LEN` can't be 0.
+fn parse_range(
+    args: &[&str],
+    default_len: usize,
+    syntax: &str,
+) -> Result<(usize, usize), CommandError> {
+    let (addr, len) = match args {
+        [addr] => (parse_addr(addr)?, default_len),
+        [addr, len] => (parse_addr(addr)?, parse_count(len)? as usize),
+        _ => return Err(usage(syntax)),
+    };
+    if len == 0 {
+        return Err(CommandError::Usage(format!(
+            "The length must be at least 1: {}",
+            args[1]
+        )));
+    }
+    Ok((addr, len.min(0x1000 - addr)))
+}
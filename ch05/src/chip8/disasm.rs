@@ -0,0 +1,55 @@
+//! Turning a ROM back into assembly.
+
+use std::fmt::Write;
+
+use super::Instruction;
+
+/// Column the annotations start at
+const COMMENT_COLUMN: usize = 20;
+
+/// A listing of `rom`, loaded at `origin`, that `asm::assemble` turns back
+/// into the same bytes.
+///
+/// Each line is annotated with its address, its bytes and, for an
+/// instruction, what it does. Words that aren't instructions, such as
+/// sprite data, are written as `DW`, and an odd last byte as `DB`.
+pub fn disassemble(rom: &[u8], origin: u16) -> String {
+    let mut listing = String::new();
+    for (i, chunk) in rom.chunks(2).enumerate() {
+        let addr = origin as usize + i * 2;
+        let (code, bytes, summary) = match *chunk {
+            [high, low] => {
+                let opcode = u16::from_be_bytes([high, low]);
+                match Instruction::decode(opcode) {
+                    Some(instruction) => (
+                        instruction.to_string(),
+                        format!("{:04X}", opcode),
+                        instruction.summary(),
+                    ),
+                    None => (
+                        format!("DW 0x{:04X}", opcode),
+                        format!("{:04X}", opcode),
+                        String::new(),
+                    ),
+                }
+            }
+            [byte] => (
+                format!("DB 0x{:02X}", byte),
+                format!("{:02X}", byte),
+                String::new(),
+            ),
+            _ => unreachable!(),
+        };
+
+        let line = format!(
+            "{:<width$}; {:03X}  {:<4}  {}",
+            code,
+            addr,
+            bytes,
+            summary,
+            width = COMMENT_COLUMN
+        );
+        writeln!(listing, "{}", line.trim_end()).unwrap();
+    }
+    listing
+}
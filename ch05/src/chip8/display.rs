@@ -0,0 +1,99 @@
+//! The 64x32 monochrome screen.
+
+use std::fmt;
+
+/// Width of the screen, in pixels
+pub const DISPLAY_WIDTH: usize = 64;
+
+/// Height of the screen, in pixels
+pub const DISPLAY_HEIGHT: usize = 32;
+
+/// The pixels of the screen, each on or off
+#[derive(Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    /// Indexed as `pixels[y][x]`
+    pixels: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+impl Framebuffer {
+    /// A blank screen
+    pub fn new() -> Self {
+        Framebuffer {
+            pixels: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+    }
+
+    /// Whether the pixel at (x, y) is on. Pixels off the screen are off.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < DISPLAY_WIDTH && y < DISPLAY_HEIGHT && self.pixels[y][x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.pixels[y][x] = on;
+    }
+
+    /// Number of pixels that are on
+    pub fn lit_pixels(&self) -> usize {
+        self.pixels.iter().flatten().filter(|&&on| on).count()
+    }
+
+    /// XORs a sprite onto the screen: each byte is a row of 8 pixels, most
+    /// significant bit on the left. The top-left corner wraps around the
+    /// screen; the rest of the sprite is clipped at its edges.
+    /// Returns whether any pixel was turned off, i.e. the sprite collided
+    /// with what was already drawn.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, rows: &[u8]) -> bool {
+        let left = x % DISPLAY_WIDTH;
+        let top = y % DISPLAY_HEIGHT;
+        let mut collision = false;
+
+        for (row, &sprite) in rows.iter().enumerate() {
+            let py = top + row;
+            if py >= DISPLAY_HEIGHT {
+                break;
+            }
+            for col in 0..8 {
+                let px = left + col;
+                if px >= DISPLAY_WIDTH {
+                    break;
+                }
+                if sprite & (0x80 >> col) != 0 {
+                    let pixel = &mut self.pixels[py][px];
+                    collision |= *pixel;
+                    *pixel = !*pixel;
+                }
+            }
+        }
+
+        collision
+    }
+}
+
+/// Draws the screen as text, two rows of pixels per line using the
+/// Unicode half blocks, so it fits in 64x16 characters of any terminal
+impl fmt::Display for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rows in self.pixels.chunks(2) {
+            let line: String = (0..DISPLAY_WIDTH)
+                .map(|x| match (rows[0][x], rows[1][x]) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                })
+                .collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
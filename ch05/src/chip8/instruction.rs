@@ -0,0 +1,265 @@
+//! The instruction set, as a single table of opcodes.
+//!
+//! `Cpu::step` decodes instructions with it, the disassembler prints them
+//! with it and the assembler encodes them with it, so the three can't
+//! disagree about what an opcode means.
+//! Mnemonics are the usual ones from Cowgod's CHIP-8 reference, plus `HALT`
+//! for `0000`.
+
+use std::fmt;
+
+/// What an instruction does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Halt,
+    Sys,
+    Cls,
+    Ret,
+    Jump,
+    Call,
+    SkipEqByte,
+    SkipNeByte,
+    SkipEqReg,
+    LoadByte,
+    AddByte,
+    LoadReg,
+    Or,
+    And,
+    Xor,
+    AddReg,
+    Sub,
+    ShiftRight,
+    SubN,
+    ShiftLeft,
+    SkipNeReg,
+    LoadIndex,
+    JumpV0,
+    Random,
+    Draw,
+    SkipKey,
+    SkipNotKey,
+    LoadDelay,
+    WaitKey,
+    SetDelay,
+    SetSound,
+    AddIndex,
+    LoadFont,
+    StoreBcd,
+    StoreRegisters,
+    LoadRegisters,
+}
+
+/// An operand of an instruction: how it is written and where it is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A register, encoded in the X nibble: `V3`
+    Vx,
+
+    /// A register, encoded in the Y nibble
+    Vy,
+
+    /// A byte, encoded in the low byte: `0x2A`
+    Byte,
+
+    /// A 4-bit number, encoded in the low nibble: `5`
+    Nibble,
+
+    /// A 12-bit address, encoded in the low three nibbles: `0x2A0`
+    Addr,
+
+    /// An operand always written the same way and not encoded: `V0`, `I`,
+    /// `[I]`, `DT`, `ST`, `K`, `F` or `B`
+    Fixed(&'static str),
+}
+
+impl Operand {
+    /// The bits of the opcode holding the operand
+    pub fn mask(self) -> u16 {
+        match self {
+            Operand::Vx => 0x0F00,
+            Operand::Vy => 0x00F0,
+            Operand::Byte => 0x00FF,
+            Operand::Nibble => 0x000F,
+            Operand::Addr => 0x0FFF,
+            Operand::Fixed(_) => 0,
+        }
+    }
+
+    /// Number of bits to shift the operand's value by to encode it
+    fn shift(self) -> u32 {
+        match self {
+            Operand::Vx => 8,
+            Operand::Vy => 4,
+            _ => 0,
+        }
+    }
+}
+
+/// An entry of the opcode table
+#[derive(Debug, PartialEq, Eq)]
+pub struct OpcodeSpec {
+    pub op: Op,
+
+    /// The opcode with all its operands zero
+    pub pattern: u16,
+
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+
+    /// What the instruction does, with `{x}`, `{y}`, `{n}`, `{nn}` and
+    /// `{nnn}` standing for its operands
+    pub summary: &'static str,
+}
+
+impl OpcodeSpec {
+    /// The bits of the opcode that aren't operands
+    pub fn mask(&self) -> u16 {
+        !self
+            .operands
+            .iter()
+            .fold(0, |mask, operand| mask | operand.mask())
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask() == self.pattern
+    }
+
+    /// The opcode with the given operand values, one for each operand.
+    /// Values of `Fixed` operands are ignored; the others must fit in their field.
+    pub fn encode(&self, values: &[u16]) -> u16 {
+        debug_assert_eq!(values.len(), self.operands.len());
+        self.operands
+            .iter()
+            .zip(values)
+            .fold(self.pattern, |opcode, (&operand, &value)| {
+                opcode | ((value << operand.shift()) & operand.mask())
+            })
+    }
+}
+
+macro_rules! spec {
+    ($op:ident, $pattern:expr, $mnemonic:expr, [$($operand:expr),*], $summary:expr) => {
+        OpcodeSpec {
+            op: Op::$op,
+            pattern: $pattern,
+            mnemonic: $mnemonic,
+            operands: &[$($operand),*],
+            summary: $summary,
+        }
+    };
+}
+
+use Operand::{Addr, Byte, Fixed, Nibble, Vx, Vy};
+
+/// Every instruction. Where opcodes overlap, the first entry that matches wins:
+/// `0000`, `00E0` and `00EE` come before `0NNN`.
+#[rustfmt::skip]
+pub static OPCODES: [OpcodeSpec; 36] = [
+    spec!(Halt, 0x0000, "HALT", [], "stop the machine"),
+    spec!(Cls, 0x00E0, "CLS", [], "clear the screen"),
+    spec!(Ret, 0x00EE, "RET", [], "return from a subroutine"),
+    spec!(Sys, 0x0000, "SYS", [Addr], "machine code routine at {nnn}, ignored"),
+    spec!(Jump, 0x1000, "JP", [Addr], "jump to {nnn}"),
+    spec!(Call, 0x2000, "CALL", [Addr], "call the subroutine at {nnn}"),
+    spec!(SkipEqByte, 0x3000, "SE", [Vx, Byte], "skip if V{x} == {nn}"),
+    spec!(SkipNeByte, 0x4000, "SNE", [Vx, Byte], "skip if V{x} != {nn}"),
+    spec!(SkipEqReg, 0x5000, "SE", [Vx, Vy], "skip if V{x} == V{y}"),
+    spec!(LoadByte, 0x6000, "LD", [Vx, Byte], "V{x} = {nn}"),
+    spec!(AddByte, 0x7000, "ADD", [Vx, Byte], "V{x} += {nn}"),
+    spec!(LoadReg, 0x8000, "LD", [Vx, Vy], "V{x} = V{y}"),
+    spec!(Or, 0x8001, "OR", [Vx, Vy], "V{x} |= V{y}, VF = 0"),
+    spec!(And, 0x8002, "AND", [Vx, Vy], "V{x} &= V{y}, VF = 0"),
+    spec!(Xor, 0x8003, "XOR", [Vx, Vy], "V{x} ^= V{y}, VF = 0"),
+    spec!(AddReg, 0x8004, "ADD", [Vx, Vy], "V{x} += V{y}, VF = carry"),
+    spec!(Sub, 0x8005, "SUB", [Vx, Vy], "V{x} -= V{y}, VF = no borrow"),
+    spec!(ShiftRight, 0x8006, "SHR", [Vx, Vy], "V{x} = V{y} >> 1, VF = bit out"),
+    spec!(SubN, 0x8007, "SUBN", [Vx, Vy], "V{x} = V{y} - V{x}, VF = no borrow"),
+    spec!(ShiftLeft, 0x800E, "SHL", [Vx, Vy], "V{x} = V{y} << 1, VF = bit out"),
+    spec!(SkipNeReg, 0x9000, "SNE", [Vx, Vy], "skip if V{x} != V{y}"),
+    spec!(LoadIndex, 0xA000, "LD", [Fixed("I"), Addr], "I = {nnn}"),
+    spec!(JumpV0, 0xB000, "JP", [Fixed("V0"), Addr], "jump to {nnn} + V0"),
+    spec!(Random, 0xC000, "RND", [Vx, Byte], "V{x} = random & {nn}"),
+    spec!(Draw, 0xD000, "DRW", [Vx, Vy, Nibble], "draw {n} rows from I at (V{x}, V{y}), VF = collision"),
+    spec!(SkipKey, 0xE09E, "SKP", [Vx], "skip if key V{x} is down"),
+    spec!(SkipNotKey, 0xE0A1, "SKNP", [Vx], "skip if key V{x} is up"),
+    spec!(LoadDelay, 0xF007, "LD", [Vx, Fixed("DT")], "V{x} = delay timer"),
+    spec!(WaitKey, 0xF00A, "LD", [Vx, Fixed("K")], "wait for a key, V{x} = key"),
+    spec!(SetDelay, 0xF015, "LD", [Fixed("DT"), Vx], "delay timer = V{x}"),
+    spec!(SetSound, 0xF018, "LD", [Fixed("ST"), Vx], "sound timer = V{x}"),
+    spec!(AddIndex, 0xF01E, "ADD", [Fixed("I"), Vx], "I += V{x}"),
+    spec!(LoadFont, 0xF029, "LD", [Fixed("F"), Vx], "I = font sprite for digit V{x}"),
+    spec!(StoreBcd, 0xF033, "LD", [Fixed("B"), Vx], "store V{x} as 3 decimal digits at I"),
+    spec!(StoreRegisters, 0xF055, "LD", [Fixed("[I]"), Vx], "store V0 to V{x} at I, I += {x} + 1"),
+    spec!(LoadRegisters, 0xF065, "LD", [Vx, Fixed("[I]")], "load V0 to V{x} from I, I += {x} + 1"),
+];
+
+/// A decoded opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u16,
+    pub spec: &'static OpcodeSpec,
+}
+
+impl Instruction {
+    /// Looks an opcode up in the table. `None` if it isn't an instruction.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        OPCODES
+            .iter()
+            .find(|spec| spec.matches(opcode))
+            .map(|spec| Instruction { opcode, spec })
+    }
+
+    pub fn op(&self) -> Op {
+        self.spec.op
+    }
+
+    pub fn x(&self) -> u8 {
+        ((self.opcode & 0x0F00) >> 8) as u8
+    }
+
+    pub fn y(&self) -> u8 {
+        ((self.opcode & 0x00F0) >> 4) as u8
+    }
+
+    pub fn n(&self) -> u8 {
+        (self.opcode & 0x000F) as u8
+    }
+
+    pub fn nn(&self) -> u8 {
+        (self.opcode & 0x00FF) as u8
+    }
+
+    pub fn nnn(&self) -> u16 {
+        self.opcode & 0x0FFF
+    }
+
+    /// What the instruction does, in words: `V0 += V1, VF = carry`
+    pub fn summary(&self) -> String {
+        self.spec
+            .summary
+            .replace("{x}", &format!("{:X}", self.x()))
+            .replace("{y}", &format!("{:X}", self.y()))
+            .replace("{nnn}", &format!("0x{:03X}", self.nnn()))
+            .replace("{nn}", &format!("0x{:02X}", self.nn()))
+            .replace("{n}", &self.n().to_string())
+    }
+}
+
+/// Assembly for the instruction: `ADD V0, V1`, `CALL 0x300`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.spec.mnemonic)?;
+        for (i, operand) in self.spec.operands.iter().enumerate() {
+            write!(f, "{}", if i == 0 { " " } else { ", " })?;
+            match operand {
+                Operand::Vx => write!(f, "V{:X}", self.x())?,
+                Operand::Vy => write!(f, "V{:X}", self.y())?,
+                Operand::Byte => write!(f, "0x{:02X}", self.nn())?,
+                Operand::Nibble => write!(f, "{}", self.n())?,
+                Operand::Addr => write!(f, "0x{:03X}", self.nnn())?,
+                Operand::Fixed(name) => write!(f, "{}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
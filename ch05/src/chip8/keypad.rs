@@ -0,0 +1,72 @@
+//! The 16-key hexadecimal keypad.
+
+/// Keyboard keys for the keypad, in the usual layout:
+///
+/// ```text
+/// keypad     keyboard
+/// 1 2 3 C    1 2 3 4
+/// 4 5 6 D    q w e r
+/// 7 8 9 E    a s d f
+/// A 0 B F    z x c v
+/// ```
+const KEYBOARD: [(char, u8); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
+/// Which of the keys 0 to F are held down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Keypad {
+    keys: [bool; 16],
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Keypad::default()
+    }
+
+    /// Presses a key, 0 to F. Higher numbers wrap around.
+    pub fn press(&mut self, key: u8) {
+        self.keys[(key & 0xF) as usize] = true;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keys[(key & 0xF) as usize] = false;
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = [false; 16];
+    }
+
+    pub fn is_down(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize]
+    }
+
+    /// The lowest key held down, if any
+    pub fn first_down(&self) -> Option<u8> {
+        self.keys.iter().position(|&down| down).map(|key| key as u8)
+    }
+
+    /// The keypad key a keyboard key stands for
+    pub fn key_for_char(c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        KEYBOARD
+            .iter()
+            .find(|&&(keyboard, _)| keyboard == c)
+            .map(|&(_, key)| key)
+    }
+}
@@ -0,0 +1,386 @@
+//! A CHIP-8 interpreter.
+//!
+//! Instructions follow the original COSMAC VIP interpreter where later
+//! interpreters disagree: the logic ops `8XY1`, `8XY2` and `8XY3` reset VF,
+//! the shifts `8XY6` and `8XYE` shift VY into VX, `FX55` and `FX65` leave I
+//! pointing past the last register, and sprites are clipped at the edges of
+//! the screen rather than wrapped around.
+//! The opcode `0000` halts the machine.
+//! Opcodes are decoded with the table in `instruction`, which the assembler
+//! and disassembler share.
+//!
+//! The delay and sound timers count down at 60 Hz whatever the instruction
+//! rate: the machine is run one frame at a time with `Cpu::run_frame`, and
+//! the timers tick once per frame.
+//!
+//! `Cpu::step` panics when the program does something impossible, such as
+//! returning with an empty stack; `Cpu::try_step` reports it as a `Fault`
+//! instead, and `debugger` is built on it.
+
+pub mod asm;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod font;
+pub mod instruction;
+pub mod keypad;
+pub mod rom;
+
+pub use debugger::Debugger;
+pub use display::{Framebuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+pub use font::{FONT, FONT_SPRITE_LEN, FONT_START};
+pub use instruction::{Instruction, Op, OPCODES};
+pub use keypad::Keypad;
+pub use rom::{RomError, MAX_ROM_LEN, PROGRAM_START};
+
+use std::{error::Error, fmt};
+
+/// Rate of the delay and sound timers, and of frames
+pub const TIMER_HZ: u32 = 60;
+
+/// Something the program did that the machine can't carry out.
+/// `addr` is the address of the instruction at fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A `CALL` with all 16 stack slots taken
+    StackOverflow { addr: usize },
+
+    /// A `RET` with no `CALL` to return from
+    StackUnderflow { addr: usize },
+
+    /// An opcode that isn't an instruction
+    InvalidOpcode { addr: usize, opcode: u16 },
+
+    /// The program counter ran past the end of memory
+    OutOfMemory { addr: usize },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::StackOverflow { addr } => write!(f, "Stack overflow at {:#05x}", addr),
+            Fault::StackUnderflow { addr } => write!(f, "Stack underflow at {:#05x}", addr),
+            Fault::InvalidOpcode { addr, opcode } => {
+                write!(f, "Invalid opcode {:04x} at {:#05x}", opcode, addr)
+            }
+            Fault::OutOfMemory { addr } => {
+                write!(f, "Program counter out of memory at {:#05x}", addr)
+            }
+        }
+    }
+}
+
+impl Error for Fault {}
+
+/// Simplified CHIP-8 CPU
+pub struct Cpu {
+    pub registers: [u8; 16],    // 16 registers
+    pub program_counter: usize, // program counter
+    pub memory: [u8; 0x1000],   // 4K RAM
+    pub stack: [u16; 16],
+    pub stack_pointer: usize,
+
+    /// The I register, holding memory addresses
+    pub index_register: u16,
+
+    /// Decremented 60 times per second until it reaches 0
+    pub delay_timer: u8,
+
+    /// Decremented 60 times per second; a tone plays while it isn't 0
+    pub sound_timer: u8,
+
+    pub display: Framebuffer,
+
+    pub keys: Keypad,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
+}
+
+impl Cpu {
+    /// A machine with the font installed and everything else zeroed,
+    /// about to run the instruction at `PROGRAM_START`
+    pub fn new() -> Self {
+        let mut memory = [0; 4096];
+        memory[FONT_START..FONT_START + FONT.len()].copy_from_slice(&FONT);
+
+        Cpu {
+            registers: [0; 16],
+            memory,
+            program_counter: PROGRAM_START,
+            stack: [0; 16],
+            stack_pointer: 0,
+            index_register: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            display: Framebuffer::new(),
+            keys: Keypad::new(),
+        }
+    }
+
+    pub fn read_opcode(&self) -> u16 {
+        let p = self.program_counter;
+        let op_byte1 = self.memory[p] as u16;
+        let op_byte2 = self.memory[p + 1] as u16;
+        (op_byte1 << 8) | op_byte2
+    }
+
+    /// Runs instructions until the halt opcode `0000`
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Runs one frame: up to `instructions` instructions, then a tick of
+    /// the timers.
+    /// Returns `false` if the machine halted during the frame.
+    pub fn run_frame(&mut self, instructions: u32) -> bool {
+        for _ in 0..instructions {
+            if !self.step() {
+                return false;
+            }
+        }
+        self.tick_timers();
+        true
+    }
+
+    /// Counts the timers down by one tick, a 60th of a second
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// Whether the tone is playing
+    pub fn is_sound_on(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Runs a single instruction.
+    /// Returns `false` if it was the halt opcode `0000`.
+    ///
+    /// # Panics
+    ///
+    /// On a `Fault`.
+    pub fn step(&mut self) -> bool {
+        match self.try_step() {
+            Ok(running) => running,
+            Err(fault) => panic!("{}", fault),
+        }
+    }
+
+    /// Runs a single instruction, like `step`, or reports why it can't.
+    /// The machine is left untouched when it can't.
+    pub fn try_step(&mut self) -> Result<bool, Fault> {
+        let addr = self.program_counter;
+        if addr + 1 >= self.memory.len() {
+            return Err(Fault::OutOfMemory { addr });
+        }
+        let opcode = self.read_opcode();
+        let instruction = match Instruction::decode(opcode) {
+            Some(instruction) => instruction,
+            None => return Err(Fault::InvalidOpcode { addr, opcode }),
+        };
+        if instruction.op() == Op::Call && self.stack_pointer >= self.stack.len() {
+            return Err(Fault::StackOverflow { addr });
+        }
+        if instruction.op() == Op::Ret && self.stack_pointer == 0 {
+            return Err(Fault::StackUnderflow { addr });
+        }
+
+        // Set the next instruction
+        self.program_counter += 2;
+
+        let x = instruction.x();
+        let y = instruction.y();
+        let nn = instruction.nn();
+        let nnn = instruction.nnn();
+        match instruction.op() {
+            Op::Halt => {
+                return Ok(false); // Termination
+            }
+            Op::Cls => self.display.clear(),
+            Op::Ret => self.ret(),
+            // Machine code routines of the original hardware can't be run
+            Op::Sys => {}
+            Op::Jump => self.jump(nnn),
+            Op::Call => self.call(nnn),
+            Op::SkipEqByte => self.skip_if(self.registers[x as usize] == nn),
+            Op::SkipNeByte => self.skip_if(self.registers[x as usize] != nn),
+            Op::SkipEqReg => self.skip_if(self.registers[x as usize] == self.registers[y as usize]),
+            Op::LoadByte => self.registers[x as usize] = nn,
+            Op::AddByte => self.add_xnn(x, nn),
+            Op::LoadReg => self.registers[x as usize] = self.registers[y as usize],
+            Op::Or => self.logic_xy(x, y, |vx, vy| vx | vy),
+            Op::And => self.logic_xy(x, y, |vx, vy| vx & vy),
+            Op::Xor => self.logic_xy(x, y, |vx, vy| vx ^ vy),
+            Op::AddReg => self.add_xy(x, y),
+            Op::Sub => self.sub_xy(x, x, y),
+            Op::ShiftRight => self.shift_right(x, y),
+            Op::SubN => self.sub_xy(x, y, x),
+            Op::ShiftLeft => self.shift_left(x, y),
+            Op::SkipNeReg => self.skip_if(self.registers[x as usize] != self.registers[y as usize]),
+            Op::LoadIndex => self.index_register = nnn,
+            Op::JumpV0 => self.jump(nnn + self.registers[0] as u16),
+            Op::Random => self.registers[x as usize] = rand::random::<u8>() & nn,
+            Op::Draw => self.draw(x, y, instruction.n()),
+            Op::SkipKey => self.skip_if(self.is_key_down(x)),
+            Op::SkipNotKey => self.skip_if(!self.is_key_down(x)),
+            Op::LoadDelay => self.registers[x as usize] = self.delay_timer,
+            Op::WaitKey => self.wait_for_key(x),
+            Op::SetDelay => self.delay_timer = self.registers[x as usize],
+            Op::SetSound => self.sound_timer = self.registers[x as usize],
+            Op::AddIndex => self.add_index(x),
+            Op::LoadFont => {
+                let digit = (self.registers[x as usize] & 0xF) as u16;
+                self.index_register = FONT_START as u16 + digit * FONT_SPRITE_LEN;
+            }
+            Op::StoreBcd => self.store_bcd(x),
+            Op::StoreRegisters => self.store_registers(x),
+            Op::LoadRegisters => self.load_registers(x),
+        }
+        Ok(true)
+    }
+
+    fn jump(&mut self, addr: u16) {
+        self.program_counter = (addr & 0xFFF) as usize;
+    }
+
+    /// Skips the next instruction if `condition` holds
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.program_counter += 2;
+        }
+    }
+
+    /// Adds without touching the carry flag
+    fn add_xnn(&mut self, x: u8, nn: u8) {
+        let vx = &mut self.registers[x as usize];
+        *vx = vx.wrapping_add(nn);
+    }
+
+    fn logic_xy(&mut self, x: u8, y: u8, op: fn(u8, u8) -> u8) {
+        self.registers[x as usize] = op(self.registers[x as usize], self.registers[y as usize]);
+        self.registers[0xF] = 0;
+    }
+
+    fn add_xy(&mut self, x: u8, y: u8) {
+        let arg1 = self.registers[x as usize];
+        let arg2 = self.registers[y as usize];
+        let (val, overflow) = arg1.overflowing_add(arg2);
+        self.registers[x as usize] = val;
+        if overflow {
+            self.registers[0xF] = 1;
+        } else {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    /// Stores `V[a] - V[b]` in `V[x]`. VF is set to 1 when there is no borrow.
+    /// The flag is written last, so it wins when `x` is F.
+    fn sub_xy(&mut self, x: u8, a: u8, b: u8) {
+        let arg1 = self.registers[a as usize];
+        let arg2 = self.registers[b as usize];
+        let (val, borrow) = arg1.overflowing_sub(arg2);
+        self.registers[x as usize] = val;
+        self.registers[0xF] = !borrow as u8;
+    }
+
+    /// Stores VY shifted right by one in VX; VF gets the bit shifted out
+    fn shift_right(&mut self, x: u8, y: u8) {
+        let vy = self.registers[y as usize];
+        self.registers[x as usize] = vy >> 1;
+        self.registers[0xF] = vy & 1;
+    }
+
+    /// Stores VY shifted left by one in VX; VF gets the bit shifted out
+    fn shift_left(&mut self, x: u8, y: u8) {
+        let vy = self.registers[y as usize];
+        self.registers[x as usize] = vy << 1;
+        self.registers[0xF] = vy >> 7;
+    }
+
+    fn add_index(&mut self, x: u8) {
+        let vx = self.registers[x as usize] as u16;
+        self.index_register = self.index_register.wrapping_add(vx) & 0xFFF;
+    }
+
+    fn is_key_down(&self, x: u8) -> bool {
+        self.keys.is_down(self.registers[x as usize])
+    }
+
+    /// Stores the lowest key held down in VX, or runs this instruction
+    /// again if there is none
+    fn wait_for_key(&mut self, x: u8) {
+        match self.keys.first_down() {
+            Some(key) => self.registers[x as usize] = key,
+            None => self.program_counter -= 2,
+        }
+    }
+
+    /// Stores the hundreds, tens and units of VX at I, I + 1 and I + 2
+    fn store_bcd(&mut self, x: u8) {
+        let vx = self.registers[x as usize];
+        let i = self.index_register as usize;
+        self.memory[i & 0xFFF] = vx / 100;
+        self.memory[(i + 1) & 0xFFF] = vx / 10 % 10;
+        self.memory[(i + 2) & 0xFFF] = vx % 10;
+    }
+
+    /// Stores V0 to VX in memory from I onwards
+    fn store_registers(&mut self, x: u8) {
+        for r in 0..=x as usize {
+            let addr = (self.index_register as usize + r) & 0xFFF;
+            self.memory[addr] = self.registers[r];
+        }
+        self.index_register = (self.index_register + x as u16 + 1) & 0xFFF;
+    }
+
+    /// Loads V0 to VX from memory from I onwards
+    fn load_registers(&mut self, x: u8) {
+        for r in 0..=x as usize {
+            let addr = (self.index_register as usize + r) & 0xFFF;
+            self.registers[r] = self.memory[addr];
+        }
+        self.index_register = (self.index_register + x as u16 + 1) & 0xFFF;
+    }
+
+    /// Draws the `n` bytes of sprite data at I at (VX, VY).
+    /// VF is set to 1 if any pixel was turned off.
+    fn draw(&mut self, x: u8, y: u8, n: u8) {
+        let rows: Vec<u8> = (0..n as usize)
+            .map(|row| self.memory[(self.index_register as usize + row) & 0xFFF])
+            .collect();
+        let collision = self.display.draw_sprite(
+            self.registers[x as usize] as usize,
+            self.registers[y as usize] as usize,
+            &rows,
+        );
+        self.registers[0xF] = collision as u8;
+    }
+
+    /// Calls the subroutine at `addr`; `try_step` has checked there is room
+    /// on the stack
+    fn call(&mut self, addr: u16) {
+        let sp = self.stack_pointer;
+        let stack = &mut self.stack;
+
+        // Save the program counter. As in run(), it is current instruction + 2
+        stack[sp] = self.program_counter as u16;
+        self.stack_pointer += 1; // The slot in the stack is taken by program counter
+
+        // Go to the specified address
+        self.program_counter = addr as usize;
+    }
+
+    /// Returns from a subroutine; `try_step` has checked there is one
+    fn ret(&mut self) {
+        // Find the position of the caller and unwind the stack
+        self.stack_pointer -= 1;
+        let call_addr = self.stack[self.stack_pointer];
+
+        // Jump to it
+        self.program_counter = call_addr as usize;
+    }
+}
@@ -0,0 +1,80 @@
+//! Loading program images ("ROMs") into memory.
+
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use super::Cpu;
+
+/// Address programs are loaded at and start running from.
+/// The memory below it held the original interpreter; here it holds the font.
+pub const PROGRAM_START: usize = 0x200;
+
+/// Largest program that fits in memory
+pub const MAX_ROM_LEN: usize = 0x1000 - PROGRAM_START;
+
+/// Errors loading a ROM
+#[derive(Debug)]
+pub enum RomError {
+    /// The ROM file couldn't be read
+    Io(io::Error),
+
+    /// The ROM has no instructions
+    Empty,
+
+    /// The ROM doesn't fit in memory after `PROGRAM_START`
+    TooLarge { len: usize },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "Cannot read ROM: {}", err),
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge { len } => write!(
+                f,
+                "ROM is {} bytes, but only {} bytes fit in memory",
+                len, MAX_ROM_LEN
+            ),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+impl Cpu {
+    /// A fresh machine with a ROM image loaded at `PROGRAM_START`
+    pub fn with_rom(rom: &[u8]) -> Result<Cpu, RomError> {
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        }
+        if rom.len() > MAX_ROM_LEN {
+            return Err(RomError::TooLarge { len: rom.len() });
+        }
+
+        let mut cpu = Cpu::new();
+        cpu.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        Ok(cpu)
+    }
+
+    /// A fresh machine with the ROM file at `path` loaded at `PROGRAM_START`
+    pub fn with_rom_file(path: &Path) -> Result<Cpu, RomError> {
+        // Don't read a huge file whole just to reject it
+        let len = fs::metadata(path)?.len();
+        if len > MAX_ROM_LEN as u64 {
+            return Err(RomError::TooLarge { len: len as usize });
+        }
+        Cpu::with_rom(&fs::read(path)?)
+    }
+}
@@ -0,0 +1,116 @@
+use ch05::chip8::{
+    asm::{self, AsmError},
+    disasm, Cpu, Instruction, Op, OPCODES, PROGRAM_START,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[test]
+fn instructions_print_as_assembly() {
+    let listed = |opcode| Instruction::decode(opcode).unwrap().to_string();
+    assert_eq!(listed(0x8014), "ADD V0, V1");
+    assert_eq!(listed(0x2100), "CALL 0x100");
+    assert_eq!(listed(0x6A2F), "LD VA, 0x2F");
+    assert_eq!(listed(0xD125), "DRW V1, V2, 5");
+    assert_eq!(listed(0xB300), "JP V0, 0x300");
+    assert_eq!(listed(0xF355), "LD [I], V3");
+    assert_eq!(listed(0x0000), "HALT");
+    assert_eq!(Instruction::decode(0x5121), None);
+    assert_eq!(Instruction::decode(0xE1A2), None);
+}
+
+#[test]
+fn every_table_entry_can_be_decoded() {
+    for spec in OPCODES.iter().filter(|spec| spec.op != Op::Sys) {
+        let instruction = Instruction::decode(spec.pattern).unwrap();
+        assert_eq!(instruction.spec, spec, "{:04X}", spec.pattern);
+    }
+    // 0NNN only shadows the 0000, 00E0 and 00EE before it
+    assert_eq!(Instruction::decode(0x0123).unwrap().op(), Op::Sys);
+}
+
+#[test]
+fn every_opcode_assembles_back_from_its_listing() {
+    for opcode in 0..=0xFFFF {
+        if let Some(instruction) = Instruction::decode(opcode) {
+            let rom = asm::assemble(&instruction.to_string()).unwrap();
+            assert_eq!(rom, opcode.to_be_bytes(), "{}", instruction);
+        }
+    }
+}
+
+#[test]
+fn disassembly_round_trips() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for len in [1, 2, 99, 1000] {
+        let rom: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        let listing = disasm::disassemble(&rom, PROGRAM_START as u16);
+        assert_eq!(asm::assemble(&listing).unwrap(), rom, "{}", listing);
+    }
+}
+
+#[test]
+fn listing_is_annotated() {
+    let listing = disasm::disassemble(&[0x80, 0x14, 0x22, 0x10, 0x51, 0x21, 0xAB], 0x200);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "ADD V0, V1          ; 200  8014  V0 += V1, VF = carry",
+            "CALL 0x210          ; 202  2210  call the subroutine at 0x210",
+            "DW 0x5121           ; 204  5121",
+            "DB 0xAB             ; 206  AB",
+        ]
+    );
+}
+
+#[test]
+fn assembled_program_runs() {
+    let source = "
+        ; Doubles V0 in a subroutine, then adds V1
+        start:  LD V0, 21
+                LD v1, 0b11
+                CALL double
+                add V0, V1
+                JP end
+        double: ADD V0, V0
+                RET
+        end:    LD I, sprite
+                HALT
+        sprite: DB 0xF0, 0x90
+                DW 0x1234
+    ";
+    let rom = asm::assemble(source).unwrap();
+    assert_eq!(rom.len(), 9 * 2 + 2 + 2);
+    assert_eq!(rom[..4], [0x60, 0x15, 0x61, 0x03]);
+    assert_eq!(rom[rom.len() - 4..], [0xF0, 0x90, 0x12, 0x34]);
+
+    let mut cpu = Cpu::with_rom(&rom).unwrap();
+    cpu.run();
+    assert_eq!(cpu.registers[0], 45);
+    assert_eq!(cpu.index_register, 0x200 + 9 * 2);
+}
+
+#[test]
+fn errors_name_their_line() {
+    let error = |source| asm::assemble(source).unwrap_err();
+    let at = |line, message: &str| AsmError {
+        line,
+        message: message.to_string(),
+    };
+
+    assert_eq!(error("CLS\nFOO V1"), at(2, "unknown instruction: FOO"));
+    assert_eq!(error("JP nowhere"), at(1, "undefined label: nowhere"));
+    assert_eq!(
+        error("LD V0, 0x100"),
+        at(1, "0x100 is out of range, the maximum is 0xFF")
+    );
+    assert_eq!(error("\n\nLD DT, 5"), at(3, "invalid operands for LD"));
+    assert_eq!(error("JP V1, 0x300"), at(1, "invalid operands for JP"));
+    assert_eq!(error("a: CLS\na: RET"), at(2, "label defined twice: a"));
+    assert_eq!(error("VA: CLS"), at(1, "invalid label name: VA"));
+    assert_eq!(error("ADD V0,"), at(1, "missing operand"));
+    assert_eq!(
+        error(&"DB 0\n".repeat(0xE01)),
+        at(0xE01, "program doesn't fit in memory")
+    );
+}
@@ -0,0 +1,222 @@
+use std::{fs, io::Cursor};
+
+use ch05::chip8::{
+    asm,
+    debugger::{Stop, CONTINUE_LIMIT},
+    Cpu, Debugger, Fault,
+};
+
+/// A debugger for a program, with the timers ticking every 10 instructions
+fn debug(source: &str) -> Debugger {
+    let rom = asm::assemble(source).unwrap();
+    Debugger::new(Cpu::with_rom(&rom).unwrap(), 10)
+}
+
+/// Runs REPL commands, one per line, returning what was printed
+fn session(debugger: &mut Debugger, commands: &str) -> String {
+    let mut output = Vec::new();
+    debugger
+        .repl(Cursor::new(commands.to_string()), &mut output)
+        .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+const COUNTDOWN: &str = "
+        LD V0, 3
+loop:   ADD V0, 0xFF
+        SE V0, 0
+        JP loop
+        LD I, 0x300
+        LD [I], V0
+        HALT
+";
+
+#[test]
+fn steps_one_instruction_at_a_time() {
+    let mut debugger = debug(COUNTDOWN);
+    assert_eq!(debugger.step(), Stop::Done);
+    assert_eq!(debugger.cpu.registers[0], 3);
+    assert_eq!(debugger.cpu.program_counter, 0x202);
+    assert_eq!(debugger.run(2), Stop::Done);
+    assert_eq!(debugger.cycles(), 3);
+}
+
+#[test]
+fn stops_at_breakpoints() {
+    let mut debugger = debug(COUNTDOWN);
+    debugger.add_breakpoint(0x202);
+    assert_eq!(debugger.run(100), Stop::Breakpoint(0x202));
+    assert_eq!(debugger.cycles(), 1);
+
+    // Running again moves off the breakpoint and comes back round the loop
+    assert_eq!(debugger.run(100), Stop::Breakpoint(0x202));
+    assert_eq!(debugger.cpu.registers[0], 2);
+
+    assert!(debugger.remove_breakpoint(0x202));
+    assert_eq!(debugger.run(100), Stop::Halted);
+    assert_eq!(debugger.step(), Stop::Halted);
+}
+
+#[test]
+fn stops_after_watched_writes() {
+    let mut debugger = debug(COUNTDOWN);
+    debugger.add_watchpoint(0x300);
+    assert_eq!(
+        debugger.run(100),
+        Stop::Watchpoint {
+            addr: 0x300,
+            pc: 0x20A,
+            old: 0,
+            new: 0
+        }
+    );
+    assert_eq!(debugger.cpu.program_counter, 0x20C);
+}
+
+#[test]
+fn faults_leave_the_machine_as_it_was() {
+    let mut debugger = debug("loop: CALL loop");
+    let stop = debugger.run(100);
+    assert_eq!(stop, Stop::Fault(Fault::StackOverflow { addr: 0x200 }));
+    assert_eq!(debugger.cycles(), 16);
+    assert_eq!(debugger.cpu.stack_pointer, 16);
+    assert_eq!(debugger.cpu.program_counter, 0x200);
+
+    let mut debugger = debug("RET");
+    assert_eq!(
+        debugger.step(),
+        Stop::Fault(Fault::StackUnderflow { addr: 0x200 })
+    );
+    let mut debugger = debug("DW 0x5121");
+    assert_eq!(
+        debugger.step(),
+        Stop::Fault(Fault::InvalidOpcode {
+            addr: 0x200,
+            opcode: 0x5121
+        })
+    );
+}
+
+#[test]
+fn continue_gives_up_on_endless_loops() {
+    let mut debugger = debug("loop: JP loop");
+    let output = session(&mut debugger, "continue\n");
+    assert!(output.contains(&format!(
+        "No breakpoint after {} instructions",
+        CONTINUE_LIMIT
+    )));
+}
+
+#[test]
+fn step_gives_up_on_endless_loops() {
+    let mut debugger = debug("loop: JP loop");
+    let output = session(&mut debugger, "step 18446744073709551615\n");
+    assert!(output.contains(&format!(
+        "No breakpoint after {} instructions",
+        CONTINUE_LIMIT
+    )));
+
+    // Smaller counts run in full, without a message
+    let output = session(&mut debugger, "step 5\n");
+    assert!(!output.contains("No breakpoint"), "{}", output);
+}
+
+#[test]
+fn trace_keeps_the_last_instructions() {
+    let mut debugger = debug(COUNTDOWN);
+    debugger.run(100);
+    let addrs: Vec<usize> = debugger.trace().map(|entry| entry.addr).collect();
+    assert_eq!(addrs.len(), 12);
+    assert_eq!(addrs[..5], [0x200, 0x202, 0x204, 0x206, 0x202]);
+    assert_eq!(addrs[addrs.len() - 3..], [0x208, 0x20A, 0x20C]);
+}
+
+#[test]
+fn timers_tick_with_the_instructions() {
+    let mut debugger = debug("LD V0, 60\nLD DT, V0\nloop: JP loop");
+    debugger.run(2);
+    assert_eq!(debugger.cpu.delay_timer, 60);
+    debugger.run(28);
+    assert_eq!(debugger.cpu.delay_timer, 57);
+}
+
+#[test]
+fn repl_session() {
+    let mut debugger = debug(COUNTDOWN);
+    let output = session(
+        &mut debugger,
+        "break 204\nc\nregs\nstack\nmem 200 4\ndis 202 2\n\nstep 2\ntrace 2\nbogus\nquit\nstep\n",
+    );
+    let expected = "\
+=> LD V0, 0x03         ; 200  6003  V0 = 0x03
+(chip8) Breakpoint at 0x204
+(chip8) Breakpoint at 0x204
+=> SE V0, 0x00         ; 204  3000  skip if V0 == 0x00
+(chip8) PC: 0x204  I: 0x000  SP: 0  DT: 0  ST: 0  cycles: 2
+V0: 0x02  V1: 0x00  V2: 0x00  V3: 0x00  V4: 0x00  V5: 0x00  V6: 0x00  V7: 0x00
+V8: 0x00  V9: 0x00  VA: 0x00  VB: 0x00  VC: 0x00  VD: 0x00  VE: 0x00  VF: 0x00
+(chip8) The stack is empty
+(chip8) 0x200: 60 03 70 ff
+(chip8)    ADD V0, 0xFF        ; 202  70FF  V0 += 0xFF
+=> SE V0, 0x00         ; 204  3000  skip if V0 == 0x00
+(chip8)    ADD V0, 0xFF        ; 202  70FF  V0 += 0xFF
+=> SE V0, 0x00         ; 204  3000  skip if V0 == 0x00
+(chip8) => ADD V0, 0xFF        ; 202  70FF  V0 += 0xFF
+(chip8)        2  0x204  3000  SE V0, 0x00
+       3  0x206  1202  JP 0x202
+(chip8) Unknown command: bogus. Type help for the list.
+(chip8) ";
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn repl_rejects_bad_arguments() {
+    let mut debugger = debug(COUNTDOWN);
+    let output = session(
+        &mut debugger,
+        "break 1000\nstep x\nmem\npress G\nwatch 0 0\nunwatch 0 0\nmem 0 0\nwatch\nbreak\n",
+    );
+    let lines: Vec<&str> = output.lines().skip(1).collect();
+    assert_eq!(
+        lines,
+        [
+            "(chip8) Not an address from 0 to fff: 1000",
+            "(chip8) Not a count: x",
+            "(chip8) Usage: mem ADDR [LEN]",
+            "(chip8) Not a key from 0 to F: G",
+            "(chip8) The length must be at least 1: 0",
+            "(chip8) The length must be at least 1: 0",
+            "(chip8) The length must be at least 1: 0",
+            "(chip8) No watchpoints",
+            "(chip8) No breakpoints",
+            "(chip8) ",
+        ]
+    );
+}
+
+#[test]
+fn repl_logs_the_instructions_run() {
+    let dir = std::env::temp_dir().join(format!("chip8-debugger-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("trace.log");
+
+    let mut debugger = debug(COUNTDOWN);
+    session(
+        &mut debugger,
+        &format!("step\nlog {}\nstep 2\nlog off\nstep\n", path.display()),
+    );
+    let log = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        log,
+        "       1  0x202  70FF  ADD V0, 0xFF\n       2  0x204  3000  SE V0, 0x00\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keys_can_be_pressed_from_the_prompt() {
+    let mut debugger = debug("LD V3, K\nHALT");
+    let output = session(&mut debugger, "step\npress b\nstep\nregs\n");
+    assert!(output.contains("Key B is down"));
+    assert!(output.contains("V3: 0x0b"));
+}
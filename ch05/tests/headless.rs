@@ -0,0 +1,135 @@
+use ch05::chip8::{Cpu, Framebuffer, Keypad, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+fn rom(program: &[u16]) -> Cpu {
+    let bytes: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    Cpu::with_rom(&bytes).unwrap()
+}
+
+/// Runs frames until the program halts, returning how many it took
+fn frames_until_halt(cpu: &mut Cpu, instructions_per_frame: u32) -> u32 {
+    let mut frames = 1;
+    while cpu.run_frame(instructions_per_frame) {
+        frames += 1;
+        assert!(frames < 1000, "program didn't halt");
+    }
+    frames
+}
+
+#[test]
+fn sprites_are_xored_and_report_collisions() {
+    let mut display = Framebuffer::new();
+    assert!(!display.draw_sprite(0, 0, &[0b1100_0000]));
+    assert!(display.pixel(0, 0) && display.pixel(1, 0));
+
+    // Overlapping in one pixel: it goes off, the other comes on
+    assert!(display.draw_sprite(1, 0, &[0b1100_0000]));
+    assert!(display.pixel(0, 0) && !display.pixel(1, 0) && display.pixel(2, 0));
+
+    // Drawing a pixel that is off is no collision
+    assert!(!display.draw_sprite(1, 0, &[0b1000_0000]));
+    assert_eq!(display.lit_pixels(), 3);
+}
+
+#[test]
+fn sprites_wrap_at_their_corner_and_clip_at_the_edges() {
+    let mut display = Framebuffer::new();
+    display.draw_sprite(DISPLAY_WIDTH + 62, DISPLAY_HEIGHT + 31, &[0xFF, 0xFF]);
+    assert!(display.pixel(62, 31) && display.pixel(63, 31));
+    assert_eq!(display.lit_pixels(), 2);
+    assert!(!display.pixel(DISPLAY_WIDTH, 0));
+}
+
+#[test]
+fn screen_renders_as_half_blocks() {
+    let mut display = Framebuffer::new();
+    display.set_pixel(0, 0, true);
+    display.set_pixel(1, 1, true);
+    display.set_pixel(2, 0, true);
+    display.set_pixel(2, 1, true);
+    display.set_pixel(63, 31, true);
+
+    let text = display.to_string();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), DISPLAY_HEIGHT / 2);
+    assert!(lines
+        .iter()
+        .all(|line| line.chars().count() == DISPLAY_WIDTH));
+    assert!(lines[0].starts_with("▀▄█ "));
+    assert!(lines[15].ends_with(" ▄"));
+}
+
+#[test]
+fn rom_output_can_be_asserted_on() {
+    // Draw the font sprite for 0 at (0, 0)
+    let mut cpu = rom(&[0x6000, 0xF029, 0xD005, 0x0000]);
+    cpu.run();
+
+    let text = cpu.display.to_string();
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    assert_eq!(lines[..4], ["█▀▀█", "█  █", "▀▀▀▀", ""]);
+}
+
+#[test]
+fn keypad_tracks_keys_held_down() {
+    let mut keys = Keypad::new();
+    assert_eq!(keys.first_down(), None);
+    keys.press(0xE);
+    keys.press(0x3);
+    assert!(keys.is_down(0x3) && keys.is_down(0xE) && !keys.is_down(0x4));
+    assert_eq!(keys.first_down(), Some(0x3));
+
+    keys.release(0x3);
+    assert_eq!(keys.first_down(), Some(0xE));
+    keys.release_all();
+    assert_eq!(keys.first_down(), None);
+}
+
+#[test]
+fn keyboard_maps_onto_the_keypad() {
+    assert_eq!(Keypad::key_for_char('1'), Some(0x1));
+    assert_eq!(Keypad::key_for_char('4'), Some(0xC));
+    assert_eq!(Keypad::key_for_char('x'), Some(0x0));
+    assert_eq!(Keypad::key_for_char('V'), Some(0xF));
+    assert_eq!(Keypad::key_for_char('p'), None);
+}
+
+#[test]
+fn held_key_is_seen_by_the_program() {
+    // Wait for a key and store it in V3
+    let mut cpu = rom(&[0xF30A, 0x0000]);
+    assert!(cpu.run_frame(10));
+    assert_eq!(cpu.program_counter, 0x200);
+
+    cpu.keys.press(0x7);
+    assert!(!cpu.run_frame(10));
+    assert_eq!(cpu.registers[3], 0x7);
+}
+
+#[test]
+fn timers_tick_once_per_frame() {
+    // Set both timers to 5, then spin
+    let mut cpu = rom(&[0x6005, 0xF015, 0xF018, 0x1206]);
+    assert!(cpu.run_frame(100));
+    assert_eq!((cpu.delay_timer, cpu.sound_timer), (4, 4));
+    assert!(cpu.is_sound_on());
+
+    for _ in 0..4 {
+        cpu.run_frame(1);
+    }
+    assert_eq!((cpu.delay_timer, cpu.sound_timer), (0, 0));
+    assert!(!cpu.is_sound_on());
+
+    // They stop at 0
+    cpu.run_frame(1);
+    assert_eq!(cpu.delay_timer, 0);
+}
+
+#[test]
+fn timers_run_at_the_same_rate_whatever_the_speed() {
+    // Set the delay timer to 30 and wait for it to run out
+    let program = [0x601E, 0xF015, 0xF107, 0x3100, 0x1204, 0x0000];
+    let slow = frames_until_halt(&mut rom(&program), 5);
+    let fast = frames_until_halt(&mut rom(&program), 500);
+    assert_eq!(slow, 31);
+    assert_eq!(fast, 31);
+}
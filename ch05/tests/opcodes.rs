@@ -0,0 +1,375 @@
+use ch05::chip8::{Cpu, DISPLAY_WIDTH, FONT_SPRITE_LEN};
+
+/// A machine with `program` loaded where it starts running
+fn load(program: &[u16]) -> Cpu {
+    let mut cpu = Cpu::new();
+    let start = cpu.program_counter;
+    for (i, opcode) in program.iter().enumerate() {
+        cpu.memory[start + 2 * i..start + 2 * i + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+    cpu
+}
+
+/// Runs the first instruction of `program` with registers preset
+fn run_one(program: &[u16], registers: &[(usize, u8)]) -> Cpu {
+    let mut cpu = load(program);
+    for &(r, value) in registers {
+        cpu.registers[r] = value;
+    }
+    assert!(cpu.step());
+    cpu
+}
+
+#[test]
+fn op_0000_halts() {
+    let mut cpu = load(&[0x0000]);
+    assert!(!cpu.step());
+}
+
+#[test]
+fn op_0nnn_is_ignored() {
+    let mut cpu = load(&[0x0123]);
+    let start = cpu.program_counter;
+    assert!(cpu.step());
+    assert_eq!(cpu.program_counter, start + 2);
+}
+
+#[test]
+fn op_00e0_clears_the_display() {
+    let mut cpu = load(&[0x00E0]);
+    cpu.display.set_pixel(7, 3, true);
+    cpu.step();
+    assert_eq!(cpu.display.lit_pixels(), 0);
+}
+
+#[test]
+fn op_2nnn_and_00ee_call_and_return() {
+    let mut cpu = load(&[0x2300]);
+    let start = cpu.program_counter;
+    cpu.memory[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+
+    cpu.step();
+    assert_eq!(cpu.program_counter, 0x300);
+    assert_eq!(cpu.stack_pointer, 1);
+    assert_eq!(cpu.stack[0] as usize, start + 2);
+
+    cpu.step();
+    assert_eq!(cpu.program_counter, start + 2);
+    assert_eq!(cpu.stack_pointer, 0);
+}
+
+#[test]
+fn op_1nnn_jumps() {
+    let cpu = run_one(&[0x1ABC], &[]);
+    assert_eq!(cpu.program_counter, 0xABC);
+}
+
+#[test]
+fn op_3xnn_skips_if_equal() {
+    let start = Cpu::new().program_counter;
+    assert_eq!(run_one(&[0x3512], &[(5, 0x12)]).program_counter, start + 4);
+    assert_eq!(run_one(&[0x3512], &[(5, 0x13)]).program_counter, start + 2);
+}
+
+#[test]
+fn op_4xnn_skips_if_not_equal() {
+    let start = Cpu::new().program_counter;
+    assert_eq!(run_one(&[0x4512], &[(5, 0x12)]).program_counter, start + 2);
+    assert_eq!(run_one(&[0x4512], &[(5, 0x13)]).program_counter, start + 4);
+}
+
+#[test]
+fn op_5xy0_skips_if_registers_equal() {
+    let start = Cpu::new().program_counter;
+    let equal = [(1, 7), (2, 7)];
+    let different = [(1, 7), (2, 8)];
+    assert_eq!(run_one(&[0x5120], &equal).program_counter, start + 4);
+    assert_eq!(run_one(&[0x5120], &different).program_counter, start + 2);
+}
+
+#[test]
+fn op_6xnn_loads_a_constant() {
+    let cpu = run_one(&[0x6A42], &[]);
+    assert_eq!(cpu.registers[0xA], 0x42);
+}
+
+#[test]
+fn op_7xnn_adds_a_constant_without_carry() {
+    let cpu = run_one(&[0x7301], &[(3, 0xFF), (0xF, 9)]);
+    assert_eq!(cpu.registers[3], 0x00);
+    assert_eq!(cpu.registers[0xF], 9);
+}
+
+#[test]
+fn op_8xy0_copies() {
+    let cpu = run_one(&[0x8120], &[(2, 0x33)]);
+    assert_eq!(cpu.registers[1], 0x33);
+}
+
+#[test]
+fn op_8xy1_ors_and_resets_vf() {
+    let cpu = run_one(&[0x8121], &[(1, 0b1100), (2, 0b1010), (0xF, 1)]);
+    assert_eq!(cpu.registers[1], 0b1110);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_8xy2_ands_and_resets_vf() {
+    let cpu = run_one(&[0x8122], &[(1, 0b1100), (2, 0b1010), (0xF, 1)]);
+    assert_eq!(cpu.registers[1], 0b1000);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_8xy3_xors_and_resets_vf() {
+    let cpu = run_one(&[0x8123], &[(1, 0b1100), (2, 0b1010), (0xF, 1)]);
+    assert_eq!(cpu.registers[1], 0b0110);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_8xy4_adds_with_carry() {
+    let cpu = run_one(&[0x8124], &[(1, 200), (2, 100)]);
+    assert_eq!(cpu.registers[1], 44);
+    assert_eq!(cpu.registers[0xF], 1);
+
+    let cpu = run_one(&[0x8124], &[(1, 20), (2, 10), (0xF, 1)]);
+    assert_eq!(cpu.registers[1], 30);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_8xy5_subtracts_with_not_borrow() {
+    let cpu = run_one(&[0x8125], &[(1, 10), (2, 3)]);
+    assert_eq!(cpu.registers[1], 7);
+    assert_eq!(cpu.registers[0xF], 1);
+
+    let cpu = run_one(&[0x8125], &[(1, 3), (2, 10)]);
+    assert_eq!(cpu.registers[1], 249);
+    assert_eq!(cpu.registers[0xF], 0);
+
+    // Equal values don't borrow
+    let cpu = run_one(&[0x8125], &[(1, 5), (2, 5)]);
+    assert_eq!(cpu.registers[1], 0);
+    assert_eq!(cpu.registers[0xF], 1);
+}
+
+#[test]
+fn op_8xy6_shifts_vy_right() {
+    let cpu = run_one(&[0x8126], &[(1, 0xFF), (2, 0b0000_0101)]);
+    assert_eq!(cpu.registers[1], 0b0000_0010);
+    assert_eq!(cpu.registers[0xF], 1);
+
+    let cpu = run_one(&[0x8126], &[(2, 0b0000_0100)]);
+    assert_eq!(cpu.registers[1], 0b0000_0010);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_8xy7_subtracts_reversed() {
+    let cpu = run_one(&[0x8127], &[(1, 3), (2, 10)]);
+    assert_eq!(cpu.registers[1], 7);
+    assert_eq!(cpu.registers[0xF], 1);
+
+    let cpu = run_one(&[0x8127], &[(1, 10), (2, 3)]);
+    assert_eq!(cpu.registers[1], 249);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_8xye_shifts_vy_left() {
+    let cpu = run_one(&[0x812E], &[(2, 0b1000_0001)]);
+    assert_eq!(cpu.registers[1], 0b0000_0010);
+    assert_eq!(cpu.registers[0xF], 1);
+
+    let cpu = run_one(&[0x812E], &[(2, 0b0100_0000)]);
+    assert_eq!(cpu.registers[1], 0b1000_0000);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn alu_flag_wins_when_vf_is_the_target() {
+    // The result is computed, then overwritten by the flag
+    let cpu = run_one(&[0x8F14], &[(0xF, 200), (1, 100)]);
+    assert_eq!(cpu.registers[0xF], 1);
+
+    let cpu = run_one(&[0x8F15], &[(0xF, 3), (1, 10)]);
+    assert_eq!(cpu.registers[0xF], 0);
+
+    let cpu = run_one(&[0x8F06], &[(0, 0b10)]);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_9xy0_skips_if_registers_differ() {
+    let start = Cpu::new().program_counter;
+    let equal = [(1, 7), (2, 7)];
+    let different = [(1, 7), (2, 8)];
+    assert_eq!(run_one(&[0x9120], &equal).program_counter, start + 2);
+    assert_eq!(run_one(&[0x9120], &different).program_counter, start + 4);
+}
+
+#[test]
+fn op_annn_loads_i() {
+    let cpu = run_one(&[0xA123], &[]);
+    assert_eq!(cpu.index_register, 0x123);
+}
+
+#[test]
+fn op_bnnn_jumps_with_v0_offset() {
+    let cpu = run_one(&[0xB300], &[(0, 0x12)]);
+    assert_eq!(cpu.program_counter, 0x312);
+}
+
+#[test]
+fn op_cxnn_masks_a_random_number() {
+    for _ in 0..32 {
+        let cpu = run_one(&[0xC50F], &[]);
+        assert_eq!(cpu.registers[5] & 0xF0, 0);
+    }
+    let cpu = run_one(&[0xC500], &[(5, 0xFF)]);
+    assert_eq!(cpu.registers[5], 0);
+}
+
+#[test]
+fn op_dxyn_xors_a_sprite_and_reports_collisions() {
+    let mut cpu = load(&[0xD122, 0xD122]);
+    cpu.index_register = 0x300;
+    cpu.memory[0x300] = 0b1100_0000;
+    cpu.memory[0x301] = 0b0000_0001;
+    cpu.registers[1] = 10;
+    cpu.registers[2] = 5;
+
+    cpu.step();
+    assert!(cpu.display.pixel(10, 5) && cpu.display.pixel(11, 5) && !cpu.display.pixel(12, 5));
+    assert!(cpu.display.pixel(17, 6));
+    assert_eq!(cpu.registers[0xF], 0);
+    assert_eq!(cpu.display.lit_pixels(), 3);
+
+    // Drawing it again erases it
+    cpu.step();
+    assert_eq!(cpu.display.lit_pixels(), 0);
+    assert_eq!(cpu.registers[0xF], 1);
+}
+
+#[test]
+fn op_dxyn_wraps_the_start_and_clips_the_rest() {
+    let mut cpu = load(&[0xD121]);
+    cpu.index_register = 0x300;
+    cpu.memory[0x300] = 0xFF;
+    // x = 62 + 64 wraps to 62; the sprite is cut off after 2 pixels
+    cpu.registers[1] = 126;
+    cpu.registers[2] = 0;
+
+    cpu.step();
+    assert!(cpu.display.pixel(62, 0) && cpu.display.pixel(63, 0));
+    assert!(!cpu.display.pixel(0, 0));
+    assert_eq!(cpu.display.lit_pixels(), 2);
+    assert_eq!(DISPLAY_WIDTH, 64);
+}
+
+#[test]
+fn op_ex9e_skips_if_key_down() {
+    let start = Cpu::new().program_counter;
+    let mut cpu = load(&[0xE39E]);
+    cpu.registers[3] = 0xB;
+    cpu.keys.press(0xB);
+    cpu.step();
+    assert_eq!(cpu.program_counter, start + 4);
+
+    let cpu = run_one(&[0xE39E], &[(3, 0xB)]);
+    assert_eq!(cpu.program_counter, start + 2);
+}
+
+#[test]
+fn op_exa1_skips_if_key_up() {
+    let start = Cpu::new().program_counter;
+    let mut cpu = load(&[0xE3A1]);
+    cpu.registers[3] = 0xB;
+    cpu.keys.press(0xB);
+    cpu.step();
+    assert_eq!(cpu.program_counter, start + 2);
+
+    let cpu = run_one(&[0xE3A1], &[(3, 0xB)]);
+    assert_eq!(cpu.program_counter, start + 4);
+}
+
+#[test]
+fn op_fx07_reads_the_delay_timer() {
+    let mut cpu = load(&[0xF407]);
+    cpu.delay_timer = 42;
+    cpu.step();
+    assert_eq!(cpu.registers[4], 42);
+}
+
+#[test]
+fn op_fx0a_waits_for_a_key() {
+    let mut cpu = load(&[0xF40A]);
+    let start = cpu.program_counter;
+    cpu.step();
+    assert_eq!(
+        cpu.program_counter, start,
+        "no key: the instruction repeats"
+    );
+
+    cpu.keys.press(0x9);
+    cpu.step();
+    assert_eq!(cpu.program_counter, start + 2);
+    assert_eq!(cpu.registers[4], 0x9);
+}
+
+#[test]
+fn op_fx15_sets_the_delay_timer() {
+    let cpu = run_one(&[0xF415], &[(4, 60)]);
+    assert_eq!(cpu.delay_timer, 60);
+}
+
+#[test]
+fn op_fx18_sets_the_sound_timer() {
+    let cpu = run_one(&[0xF418], &[(4, 30)]);
+    assert_eq!(cpu.sound_timer, 30);
+}
+
+#[test]
+fn op_fx1e_adds_to_i() {
+    let mut cpu = load(&[0xF41E]);
+    cpu.index_register = 0x100;
+    cpu.registers[4] = 0x20;
+    cpu.step();
+    assert_eq!(cpu.index_register, 0x120);
+}
+
+#[test]
+fn op_fx29_points_i_at_a_font_sprite() {
+    let cpu = run_one(&[0xF429], &[(4, 0xA)]);
+    assert_eq!(cpu.index_register, 0xA * FONT_SPRITE_LEN);
+}
+
+#[test]
+fn op_fx33_stores_bcd() {
+    let mut cpu = load(&[0xF433]);
+    cpu.index_register = 0x300;
+    cpu.registers[4] = 254;
+    cpu.step();
+    assert_eq!(cpu.memory[0x300..0x303], [2, 5, 4]);
+}
+
+#[test]
+fn op_fx55_stores_registers() {
+    let mut cpu = load(&[0xF255]);
+    cpu.index_register = 0x300;
+    cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+    cpu.step();
+    assert_eq!(cpu.memory[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(cpu.index_register, 0x303);
+}
+
+#[test]
+fn op_fx65_loads_registers() {
+    let mut cpu = load(&[0xF265]);
+    cpu.index_register = 0x300;
+    cpu.memory[0x300..0x304].copy_from_slice(&[9, 8, 7, 6]);
+    cpu.step();
+    assert_eq!(cpu.registers[..4], [9, 8, 7, 0]);
+    assert_eq!(cpu.index_register, 0x303);
+}
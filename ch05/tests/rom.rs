@@ -0,0 +1,54 @@
+use std::fs;
+
+use ch05::chip8::{Cpu, RomError, FONT, FONT_START, MAX_ROM_LEN, PROGRAM_START};
+
+#[test]
+fn rom_is_loaded_at_the_entry_point() {
+    let cpu = Cpu::with_rom(&[0x60, 0x2A, 0x00, 0x00]).unwrap();
+    assert_eq!(cpu.program_counter, PROGRAM_START);
+    assert_eq!(cpu.memory[0x200..0x204], [0x60, 0x2A, 0x00, 0x00]);
+}
+
+#[test]
+fn font_is_installed_below_the_program() {
+    let cpu = Cpu::with_rom(&[0x00, 0x00]).unwrap();
+    assert_eq!(cpu.memory[FONT_START..FONT_START + FONT.len()], FONT);
+    assert_eq!(FONT_START + FONT.len(), 0x050);
+}
+
+#[test]
+fn largest_rom_fills_memory() {
+    let cpu = Cpu::with_rom(&vec![0xAB; MAX_ROM_LEN]).unwrap();
+    assert_eq!(cpu.memory[0xFFF], 0xAB);
+}
+
+#[test]
+fn oversized_rom_is_rejected() {
+    let err = Cpu::with_rom(&vec![0; MAX_ROM_LEN + 1]).err().unwrap();
+    assert!(matches!(err, RomError::TooLarge { len } if len == MAX_ROM_LEN + 1));
+    assert!(matches!(Cpu::with_rom(&[]), Err(RomError::Empty)));
+}
+
+#[test]
+fn rom_file_is_read_from_disk() {
+    let dir = std::env::temp_dir().join(format!("chip8-rom-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("digit.ch8");
+    // Point I at the sprite for 7 and copy its first row to V0
+    fs::write(&path, [0x61, 0x07, 0xF1, 0x29, 0xF0, 0x65, 0x00, 0x00]).unwrap();
+    let mut cpu = Cpu::with_rom_file(&path).unwrap();
+    cpu.run();
+    assert_eq!(cpu.registers[0], FONT[7 * 5]);
+
+    let big = dir.join("big.ch8");
+    fs::write(&big, vec![0; MAX_ROM_LEN + 2]).unwrap();
+    assert!(matches!(
+        Cpu::with_rom_file(&big),
+        Err(RomError::TooLarge { .. })
+    ));
+    assert!(matches!(
+        Cpu::with_rom_file(&dir.join("missing.ch8")),
+        Err(RomError::Io(_))
+    ));
+}
@@ -0,0 +1,37 @@
+use super::kv::RecordKind;
+
+/// A group of writes applied atomically by `ActionKv::write_batch`:
+/// after a crash, either all of them are visible or none is.
+#[derive(Debug, Default)]
+pub struct WriteBatch {
+    pub(crate) ops: Vec<(RecordKind, Vec<u8>, Vec<u8>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    /// Set `key` to `value` when the batch is written
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.ops
+            .push((RecordKind::Put, key.to_vec(), value.to_vec()));
+        self
+    }
+
+    /// Delete `key` when the batch is written
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.ops
+            .push((RecordKind::Tombstone, key.to_vec(), Vec::new()));
+        self
+    }
+
+    /// Number of writes in the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
@@ -0,0 +1,204 @@
+//! Bloom filters over the keys of each data file, so that looking up a key
+//! that was never written doesn't need to read the file.
+//!
+//! A filter is saved next to its data file. Layout (all integers little-endian):
+//! magic, length of the data file covered by the filter, number of keys it
+//! was sized for, number of keys added, number of hash functions, number of
+//! 64-bit words, the words, then a CRC32 of everything before it.
+
+use std::{
+    f64::consts::LN_2,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+const BLOOM_MAGIC: &[u8; 8] = b"AKVBLOOM";
+
+/// Filters are never sized for fewer keys than this
+const MIN_CAPACITY: u64 = 1024;
+
+/// A set of keys that can answer "definitely not in the set" or
+/// "possibly in the set"
+#[derive(Debug, Clone)]
+pub(crate) struct BloomFilter {
+    words: Vec<u64>,
+
+    /// Number of bit positions checked for each key
+    hashes: u32,
+
+    /// Number of keys the filter was sized for.
+    /// Past it, the false-positive rate climbs above the one asked for.
+    capacity: u64,
+
+    /// Number of keys added, counting keys added twice
+    keys: u64,
+}
+
+impl BloomFilter {
+    /// An empty filter with a false-positive rate of about `false_positive_rate`
+    /// once `capacity` keys have been added
+    pub(crate) fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let bits = (-(capacity as f64) * rate.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let words = bits.div_ceil(64).max(1);
+        let hashes = ((words * 64) as f64 / capacity as f64 * LN_2).round();
+        BloomFilter {
+            words: vec![0; words as usize],
+            hashes: hashes.clamp(1.0, 32.0) as u32,
+            capacity,
+            keys: 0,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_positions(key) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.keys += 1;
+    }
+
+    /// `false` if the key was never added; `true` if it may have been
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether more keys have been added than the filter was sized for
+    pub(crate) fn is_full(&self) -> bool {
+        self.keys >= self.capacity
+    }
+
+    pub(crate) fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub(crate) fn bits(&self) -> u64 {
+        self.words.len() as u64 * 64
+    }
+
+    /// Estimated probability that `may_contain` is true for a key that
+    /// wasn't added, from the fraction of bits set
+    pub(crate) fn false_positive_rate(&self) -> f64 {
+        let set: u32 = self.words.iter().map(|word| word.count_ones()).sum();
+        (set as f64 / self.bits() as f64).powi(self.hashes as i32)
+    }
+
+    /// The bits of a key: `hashes` positions derived from two hashes of it
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let h1 = fnv1a(key);
+        // Odd, so that the positions don't repeat before covering the filter
+        let h2 = mix(h1) | 1;
+        let bits = self.bits();
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+}
+
+/// FNV-1a: unlike the standard library's hashers, it is guaranteed to stay
+/// the same, which saved filters rely on
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// The finalizer of SplitMix64, to derive a second, independent-looking hash
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Path of the filter file belonging to a data file
+pub(crate) fn bloom_path(data_path: &Path) -> PathBuf {
+    let mut path = data_path.to_path_buf().into_os_string();
+    path.push(".bloom");
+    PathBuf::from(path)
+}
+
+/// Writes a filter file atomically: readers see either the old filter or the new one.
+/// `data_len` is the length of the data file the filter covers.
+pub(crate) fn write_filter(path: &Path, data_len: u64, filter: &BloomFilter) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(48 + filter.words.len() * 8);
+    buf.write_all(BLOOM_MAGIC)?;
+    buf.write_u64::<LittleEndian>(data_len)?;
+    buf.write_u64::<LittleEndian>(filter.capacity)?;
+    buf.write_u64::<LittleEndian>(filter.keys)?;
+    buf.write_u32::<LittleEndian>(filter.hashes)?;
+    buf.write_u64::<LittleEndian>(filter.words.len() as u64)?;
+    for &word in &filter.words {
+        buf.write_u64::<LittleEndian>(word)?;
+    }
+    let checksum = super::kv::checksum(&buf);
+    buf.write_u32::<LittleEndian>(checksum)?;
+
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.write_all(&buf)?;
+    let f = writer.into_inner().map_err(|e| e.into_error())?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads a filter file, with the length of the data file it covers.
+/// Returns `None` if there is no filter or it is damaged, in which case
+/// it must be built again from the index.
+pub(crate) fn read_filter(path: &Path) -> io::Result<Option<(u64, BloomFilter)>> {
+    let mut buf = Vec::new();
+    match File::open(path) {
+        Ok(f) => BufReader::new(f).read_to_end(&mut buf)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if buf.len() < BLOOM_MAGIC.len() + 8 + 8 + 8 + 4 + 8 + 4 {
+        return Ok(None);
+    }
+    let (body, trailer) = buf.split_at(buf.len() - 4);
+    let saved_checksum = u32::from_le_bytes(trailer.try_into().unwrap());
+    if super::kv::checksum(body) != saved_checksum || &body[..BLOOM_MAGIC.len()] != BLOOM_MAGIC {
+        return Ok(None);
+    }
+
+    let mut reader = &body[BLOOM_MAGIC.len()..];
+    let data_len = reader.read_u64::<LittleEndian>()?;
+    let capacity = reader.read_u64::<LittleEndian>()?;
+    let keys = reader.read_u64::<LittleEndian>()?;
+    let hashes = reader.read_u32::<LittleEndian>()?;
+    let count = reader.read_u64::<LittleEndian>()?;
+    // A damaged count can be too large to multiply
+    if count == 0 || hashes == 0 || count.checked_mul(8) != Some(reader.len() as u64) {
+        return Ok(None);
+    }
+    let mut words = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        words.push(reader.read_u64::<LittleEndian>()?);
+    }
+
+    Ok(Some((
+        data_len,
+        BloomFilter {
+            words,
+            hashes,
+            capacity,
+            keys,
+        },
+    )))
+}
+
+/// Deletes the filter of a data file, if there is one
+pub(crate) fn remove_filter(data_path: &Path) -> io::Result<()> {
+    match fs::remove_file(bloom_path(data_path)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
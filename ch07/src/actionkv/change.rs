@@ -0,0 +1,338 @@
+//! Change feeds: being told about writes as they happen.
+//!
+//! In the process that owns a store, `ActionKv::subscribe` hands out a channel
+//! receiving a `Change` for every put and delete. Other processes can follow
+//! the log itself with a `Tail`, which decodes records as they are appended.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::kv::{ActionKv, Position, RecordKind};
+use super::segment;
+use super::{KvError, KvOptions};
+
+type ByteString = Vec<u8>;
+
+/// What a write did to a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// The key was set to a value
+    Put {
+        value: ByteString,
+
+        /// When the key expires, in milliseconds since the Unix epoch
+        expires_at: Option<u64>,
+    },
+
+    /// The key was deleted
+    Delete,
+}
+
+/// A write to the store, as seen by a subscriber or a `Tail`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub key: ByteString,
+    pub op: Op,
+
+    /// Segment of the record written. Always 0 for a single-file store.
+    pub segment: u64,
+
+    /// Offset of the record written within its segment
+    pub offset: u64,
+}
+
+impl Change {
+    /// Where the record of the change starts in the log
+    pub fn position(&self) -> Position {
+        Position {
+            segment: self.segment,
+            offset: self.offset,
+        }
+    }
+}
+
+/// Follows the log of a store like `tail -f`, decoding records as they are
+/// appended by whichever process writes to it.
+///
+/// No lock is taken, so a store can be followed while it is open for writing.
+/// Records of a write batch are only returned once its commit record has been
+/// appended. In a segmented store, the tail moves on to the next segment once
+/// the writer has rolled over to it; if the segments it hasn't reached yet are
+/// compacted meanwhile, their live records are read again as puts.
+/// A single-file store is compacted by replacing its file; the tail then
+/// starts over on the new file, so its live records are read again as puts
+/// and deletes not read yet are missed.
+/// The same file can also be cut short, when `recover` drops a torn tail or
+/// a writer drops an uncommitted batch. The records the tail already returned
+/// are still there and are not returned again. A cut is only noticed while
+/// the file is shorter than the offset reached, so a tail should be polled
+/// more often than the writer can write back past it.
+#[derive(Debug)]
+pub struct Tail {
+    /// The store file, or the directory of a segmented store
+    path: PathBuf,
+
+    /// Directory of a segmented store
+    dir: Option<PathBuf>,
+
+    f: File,
+    segment: u64,
+    version: u32,
+
+    /// Offset of the next record to read
+    offset: u64,
+
+    /// Changes before this position are read but not returned
+    start: Position,
+
+    /// Records of a write batch whose commit hasn't been read yet,
+    /// with the offset of its begin record and the number of records
+    /// the batch announced
+    batch: Option<(u64, u32, Vec<Change>)>,
+
+    /// Committed changes read but not returned yet
+    ready: VecDeque<Change>,
+
+    /// Offset of a record that failed its checksum when last read.
+    /// It may have been caught half-written; if it still fails when read
+    /// again, it is reported as corrupted.
+    suspect: Option<u64>,
+
+    /// Whether a newer segment was seen after reaching the end of this one
+    rolled_over: bool,
+}
+
+impl Tail {
+    /// Starts following the store at `path`, a file or a directory of segments,
+    /// from the first record at or after `from`.
+    /// The segment is read from its start, so that `from` needs not be the
+    /// offset of a record and batches are still framed correctly.
+    pub fn open(path: &Path, from: Position) -> Result<Tail, KvError> {
+        let dir = path.is_dir().then(|| path.to_path_buf());
+        let mut segment = 0;
+        if let Some(dir) = &dir {
+            let ids = segment::list_segments(dir)?;
+            // A segment that is gone was compacted into a newer one
+            segment = match ids.iter().find(|&&id| id >= from.segment) {
+                Some(&id) => id,
+                None => ids.last().copied().unwrap_or(from.segment),
+            };
+        }
+
+        let (f, version) = Tail::open_segment(dir.as_deref(), path, segment)?;
+        Ok(Tail {
+            path: path.to_path_buf(),
+            dir,
+            f,
+            segment,
+            version,
+            offset: ActionKv::data_start_of(version),
+            start: from,
+            batch: None,
+            ready: VecDeque::new(),
+            suspect: None,
+            rolled_over: false,
+        })
+    }
+
+    fn open_segment(dir: Option<&Path>, path: &Path, id: u64) -> Result<(File, u32), KvError> {
+        let path = match dir {
+            Some(dir) => segment::segment_path(dir, id),
+            None => path.to_path_buf(),
+        };
+        let mut f = File::open(path)?;
+        let options = KvOptions {
+            read_only: true,
+            ..KvOptions::default()
+        };
+        let (version, _) = ActionKv::read_or_init_header(&mut f, &options)?;
+        Ok((f, version))
+    }
+
+    /// Whether the store followed is a directory of segment files
+    pub fn is_segmented(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Where the next record will be read from
+    pub fn position(&self) -> Position {
+        Position {
+            segment: self.segment,
+            offset: self.offset,
+        }
+    }
+
+    /// Returns the next committed change, or `None` if there is none yet.
+    /// Call it again later to pick up records appended in the meantime.
+    pub fn next_change(&mut self) -> Result<Option<Change>, KvError> {
+        loop {
+            while let Some(change) = self.ready.pop_front() {
+                if change.position() >= self.start {
+                    return Ok(Some(change));
+                }
+            }
+
+            let record = match ActionKv::read_record_at(&self.f, self.offset, self.version) {
+                Ok(record) => record,
+                Err(KvError::TornRecord { .. }) => {
+                    if self.roll_over()? || self.reopen_if_replaced()? {
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                Err(err @ KvError::Corruption { .. }) => {
+                    if self.suspect == Some(self.offset) {
+                        return Err(err);
+                    }
+                    self.suspect = Some(self.offset);
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            };
+            self.suspect = None;
+
+            let offset = self.offset;
+            self.offset += record.len;
+            let op = match record.kind {
+                RecordKind::Put => Op::Put {
+                    value: record.value,
+                    expires_at: record.expires_at,
+                },
+                RecordKind::Tombstone => Op::Delete,
+                RecordKind::BatchBegin => {
+                    if self.batch.is_some() {
+                        return Err(ActionKv::invalid_batch(offset));
+                    }
+                    let count = ActionKv::batch_count(&record.value, offset)?;
+                    self.batch = Some((offset, count, Vec::new()));
+                    continue;
+                }
+                RecordKind::BatchCommit => {
+                    let count = ActionKv::batch_count(&record.value, offset)?;
+                    match self.batch.take() {
+                        Some((_, expected, changes))
+                            if expected == count && changes.len() == count as usize =>
+                        {
+                            self.ready.extend(changes);
+                        }
+                        _ => return Err(ActionKv::invalid_batch(offset)),
+                    }
+                    continue;
+                }
+            };
+
+            let change = Change {
+                key: record.key,
+                op,
+                segment: self.segment,
+                offset,
+            };
+            match self.batch.as_mut() {
+                Some((_, _, changes)) => changes.push(change),
+                None => self.ready.push_back(change),
+            }
+        }
+    }
+
+    /// Starts over on the store file if compaction has replaced it since it
+    /// was opened. The old file is finished: the writer has moved on to the
+    /// new one, which has different offsets.
+    /// If the same file was cut short instead, it is read again from its
+    /// start, to frame batches correctly, returning only changes past
+    /// the ones returned already.
+    fn reopen_if_replaced(&mut self) -> Result<bool, KvError> {
+        if self.dir.is_some() {
+            return Ok(false);
+        }
+        let current = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // Between the removal of the old file and the rename of the new one
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let same = same_file(&current, &self.f.metadata()?);
+        if current.len() >= self.offset && same {
+            return Ok(false);
+        }
+
+        if same {
+            // Only torn or uncommitted records are cut, so every change
+            // returned is before the cut, and every record past it is new
+            let unreturned = self
+                .ready
+                .iter()
+                .map(|change| change.offset)
+                .chain(self.batch.as_ref().map(|&(begin, _, _)| begin))
+                .min()
+                .unwrap_or(self.offset);
+            self.start.offset = self.start.offset.max(unreturned).min(current.len());
+            self.offset = ActionKv::data_start_of(self.version);
+            self.ready.clear();
+            self.batch = None;
+            self.suspect = None;
+            return Ok(true);
+        }
+
+        let (f, version) = Tail::open_segment(None, &self.path, 0)?;
+        self.f = f;
+        self.version = version;
+        self.offset = ActionKv::data_start_of(version);
+        self.start = Position {
+            segment: 0,
+            offset: 0,
+        };
+        self.batch = None;
+        self.suspect = None;
+        Ok(true)
+    }
+
+    /// Moves to the next segment once the current one is finished.
+    /// A writer only creates a segment after its last write to the previous
+    /// one, so the current segment is read to its end once more after a newer
+    /// segment shows up.
+    fn roll_over(&mut self) -> Result<bool, KvError> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(false),
+        };
+        let next = segment::list_segments(dir)?
+            .into_iter()
+            .find(|&id| id > self.segment);
+        let next = match next {
+            Some(next) => next,
+            None => return Ok(false),
+        };
+        if !self.rolled_over {
+            self.rolled_over = true;
+            return Ok(true);
+        }
+
+        let (f, version) = Tail::open_segment(Some(dir), dir, next)?;
+        self.f = f;
+        self.segment = next;
+        self.version = version;
+        self.offset = ActionKv::data_start_of(version);
+        self.batch = None;
+        self.rolled_over = false;
+        Ok(true)
+    }
+}
+
+/// Whether two files are the same file, not just at the same path
+#[cfg(not(windows))]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+/// Windows doesn't tell without opening the file for more than reading, so a
+/// replaced file is only noticed when it is shorter than what was read.
+#[cfg(windows)]
+fn same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    true
+}
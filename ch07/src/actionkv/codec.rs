@@ -0,0 +1,65 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::KvError;
+
+/// Serialization format of the keys and values of a `TypedKv` store.
+/// The codec is recorded in the file header when a store is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    Bincode,
+    Cbor,
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, KvError> {
+        let encoded = match self {
+            Codec::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Codec::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            Codec::Cbor => serde_cbor::to_vec(value).map_err(|e| e.to_string()),
+        };
+        encoded.map_err(KvError::Codec)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, KvError> {
+        let decoded = match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Codec::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+            Codec::Cbor => serde_cbor::from_slice(bytes).map_err(|e| e.to_string()),
+        };
+        decoded.map_err(KvError::Codec)
+    }
+
+    /// The byte stored in the file header. 0 means the store holds raw bytes.
+    pub(crate) fn to_header_byte(codec: Option<Codec>) -> u8 {
+        match codec {
+            None => 0,
+            Some(Codec::Json) => 1,
+            Some(Codec::Bincode) => 2,
+            Some(Codec::Cbor) => 3,
+        }
+    }
+
+    pub(crate) fn from_header_byte(byte: u8) -> Option<Option<Codec>> {
+        match byte {
+            0 => Some(None),
+            1 => Some(Some(Codec::Json)),
+            2 => Some(Some(Codec::Bincode)),
+            3 => Some(Some(Codec::Cbor)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Codec::Json => "json",
+            Codec::Bincode => "bincode",
+            Codec::Cbor => "cbor",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,98 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use super::{Codec, Position};
+
+/// Errors returned by the key-value store
+#[derive(Debug)]
+pub enum KvError {
+    /// An I/O error from the backing file
+    Io(io::Error),
+
+    /// The record at `offset` does not match its stored checksum
+    Corruption {
+        offset: u64,
+        expected: u32,
+        actual: u32,
+    },
+
+    /// The record at `offset` runs past the end of the file,
+    /// typically because a write was interrupted by a crash
+    TornRecord { offset: u64 },
+
+    /// Another handle holds a conflicting lock on the store file
+    Locked { path: PathBuf },
+
+    /// A write was attempted on a store opened read-only
+    ReadOnly,
+
+    /// The store was created with a different codec than the one requested
+    CodecMismatch {
+        expected: Option<Codec>,
+        found: Option<Codec>,
+    },
+
+    /// A key or value could not be serialized or deserialized
+    Codec(String),
+
+    /// A position is not in the log: its segment was compacted away, or it
+    /// lies past the end of its file or within its header
+    NoSuchPosition { position: Position },
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::Io(err) => write!(f, "I/O error: {}", err),
+            KvError::Corruption {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Data corruption at offset {} (checksum {:08x} != {:08x})",
+                offset, actual, expected
+            ),
+            KvError::TornRecord { offset } => {
+                write!(f, "Incomplete record at offset {}", offset)
+            }
+            KvError::Locked { path } => {
+                write!(f, "{} is locked by another process", path.display())
+            }
+            KvError::ReadOnly => write!(f, "Store was opened read-only"),
+            KvError::CodecMismatch { expected, found } => write!(
+                f,
+                "Store uses codec {} but {} was requested",
+                codec_name(found),
+                codec_name(expected)
+            ),
+            KvError::Codec(message) => write!(f, "Codec error: {}", message),
+            KvError::NoSuchPosition { position } => write!(
+                f,
+                "Offset {} of segment {} is not in the log; it may have been compacted away",
+                position.offset, position.segment
+            ),
+        }
+    }
+}
+
+fn codec_name(codec: &Option<Codec>) -> String {
+    match codec {
+        None => "raw".to_string(),
+        Some(codec) => codec.to_string(),
+    }
+}
+
+impl Error for KvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            KvError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KvError {
+    fn from(err: io::Error) -> Self {
+        KvError::Io(err)
+    }
+}
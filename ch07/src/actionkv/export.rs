@@ -0,0 +1,157 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+use super::{ActionKv, KvError, WriteBatch};
+
+/// Text formats to move the live contents of a store in and out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    Jsonl,
+
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Unknown format {:?}, expected jsonl or csv", s)),
+        }
+    }
+}
+
+/// How the key and value of an exported record are written as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    /// Both are valid UTF-8 and written as they are
+    #[default]
+    Utf8,
+
+    /// At least one is not UTF-8: both are written in base64
+    Base64,
+}
+
+/// A line of JSONL or a row of CSV
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportRecord {
+    key: String,
+    value: String,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+impl ExportRecord {
+    fn encode(key: &[u8], value: &[u8]) -> Self {
+        match (std::str::from_utf8(key), std::str::from_utf8(value)) {
+            (Ok(key), Ok(value)) => ExportRecord {
+                key: key.to_string(),
+                value: value.to_string(),
+                encoding: Encoding::Utf8,
+            },
+            _ => ExportRecord {
+                key: BASE64.encode(key),
+                value: BASE64.encode(value),
+                encoding: Encoding::Base64,
+            },
+        }
+    }
+
+    fn decode(self) -> Result<(Vec<u8>, Vec<u8>), String> {
+        match self.encoding {
+            Encoding::Utf8 => Ok((self.key.into_bytes(), self.value.into_bytes())),
+            Encoding::Base64 => {
+                let key = BASE64.decode(self.key).map_err(|e| e.to_string())?;
+                let value = BASE64.decode(self.value).map_err(|e| e.to_string())?;
+                Ok((key, value))
+            }
+        }
+    }
+}
+
+/// Writes every live (key, value) pair, in key order.
+/// Returns the number of pairs written.
+pub fn export<W: Write>(
+    store: &ActionKv,
+    format: ExportFormat,
+    writer: W,
+) -> Result<usize, KvError> {
+    let mut count = 0;
+    match format {
+        ExportFormat::Jsonl => {
+            let mut writer = writer;
+            for kv in store.iter() {
+                let kv = kv?;
+                let record = ExportRecord::encode(&kv.key, &kv.value);
+                serde_json::to_writer(&mut writer, &record)
+                    .map_err(|e| KvError::Codec(e.to_string()))?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for kv in store.iter() {
+                let kv = kv?;
+                writer
+                    .serialize(ExportRecord::encode(&kv.key, &kv.value))
+                    .map_err(|e| KvError::Codec(e.to_string()))?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+/// Reads pairs written by `export` and stores them with a single write batch,
+/// so that either the whole file is imported or nothing is.
+/// Returns the number of pairs imported.
+pub fn import<R: Read>(
+    store: &mut ActionKv,
+    format: ExportFormat,
+    reader: R,
+) -> Result<usize, KvError> {
+    let mut batch = WriteBatch::new();
+    match format {
+        ExportFormat::Jsonl => {
+            for (i, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (key, value) = serde_json::from_str::<ExportRecord>(&line)
+                    .map_err(|e| e.to_string())
+                    .and_then(ExportRecord::decode)
+                    .map_err(|e| KvError::Codec(format!("line {}: {}", i + 1, e)))?;
+                batch.put(&key, &value);
+            }
+        }
+        ExportFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            for (i, record) in reader.deserialize::<ExportRecord>().enumerate() {
+                // Row 1 is the header
+                let (key, value) = record
+                    .map_err(|e| e.to_string())
+                    .and_then(ExportRecord::decode)
+                    .map_err(|e| KvError::Codec(format!("row {}: {}", i + 2, e)))?;
+                batch.put(&key, &value);
+            }
+        }
+    }
+
+    let count = batch.len();
+    store.write_batch(batch)?;
+    Ok(count)
+}
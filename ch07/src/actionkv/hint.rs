@@ -0,0 +1,137 @@
+//! Hint files store a copy of the index next to the data file, so that
+//! opening a store does not need to read every record.
+//!
+//! Layout (all integers little-endian):
+//! magic, format version of the data file, length of the data file covered
+//! by the hint, fingerprint of the data file, number of entries, then for
+//! each entry the key length,
+//! record offset, record length, expiry time (0 if the key doesn't expire)
+//! and key bytes.
+//! A CRC32 of everything before it closes the file.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::kv::Location;
+
+/// Older hints, without expiry times or a fingerprint, are ignored
+const HINT_MAGIC: &[u8; 8] = b"AKVHINT3";
+
+/// The contents of a hint file
+#[derive(Debug)]
+pub(crate) struct Hint {
+    /// Length of the data file when the hint was written.
+    /// Records after this offset are not covered by the hint.
+    pub data_len: u64,
+
+    /// Checksum of some of the data covered, see `ActionKv::fingerprint`.
+    /// A data file that doesn't match is not the one the hint was written for.
+    pub fingerprint: u32,
+
+    pub entries: Vec<(Vec<u8>, Location)>,
+}
+
+/// Path of the hint file belonging to a data file
+pub(crate) fn hint_path(data_path: &Path) -> PathBuf {
+    let mut path = data_path.to_path_buf().into_os_string();
+    path.push(".hint");
+    PathBuf::from(path)
+}
+
+/// Writes a hint file atomically: readers see either the old hint or the new one
+pub(crate) fn write_hint<'a, I>(
+    path: &Path,
+    version: u32,
+    data_len: u64,
+    fingerprint: u32,
+    entries: I,
+) -> io::Result<()>
+where
+    I: ExactSizeIterator<Item = (&'a Vec<u8>, &'a Location)>,
+{
+    let mut buf = Vec::new();
+    buf.write_all(HINT_MAGIC)?;
+    buf.write_u32::<LittleEndian>(version)?;
+    buf.write_u64::<LittleEndian>(data_len)?;
+    buf.write_u32::<LittleEndian>(fingerprint)?;
+    buf.write_u64::<LittleEndian>(entries.len() as u64)?;
+    for (key, location) in entries {
+        buf.write_u32::<LittleEndian>(key.len() as u32)?;
+        buf.write_u64::<LittleEndian>(location.offset)?;
+        buf.write_u64::<LittleEndian>(location.len)?;
+        buf.write_u64::<LittleEndian>(location.expires_at.unwrap_or(0))?;
+        buf.write_all(key)?;
+    }
+    let checksum = super::kv::checksum(&buf);
+    buf.write_u32::<LittleEndian>(checksum)?;
+
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.write_all(&buf)?;
+    let f = writer.into_inner().map_err(|e| e.into_error())?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads a hint file.
+/// Returns `None` if there is no hint, or if it is damaged or was written
+/// for a different format version, in which case the data file must be scanned.
+pub(crate) fn read_hint(path: &Path, version: u32) -> io::Result<Option<Hint>> {
+    let mut buf = Vec::new();
+    match File::open(path) {
+        Ok(f) => BufReader::new(f).read_to_end(&mut buf)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if buf.len() < HINT_MAGIC.len() + 4 + 8 + 4 + 8 + 4 {
+        return Ok(None);
+    }
+    let (body, trailer) = buf.split_at(buf.len() - 4);
+    let saved_checksum = u32::from_le_bytes(trailer.try_into().unwrap());
+    if super::kv::checksum(body) != saved_checksum || &body[..HINT_MAGIC.len()] != HINT_MAGIC {
+        return Ok(None);
+    }
+
+    let mut reader = &body[HINT_MAGIC.len()..];
+    if reader.read_u32::<LittleEndian>()? != version {
+        return Ok(None);
+    }
+    let data_len = reader.read_u64::<LittleEndian>()?;
+    let fingerprint = reader.read_u32::<LittleEndian>()?;
+    let count = reader.read_u64::<LittleEndian>()?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let key_len = reader.read_u32::<LittleEndian>()?;
+        let offset = reader.read_u64::<LittleEndian>()?;
+        let len = reader.read_u64::<LittleEndian>()?;
+        let expires_at = reader.read_u64::<LittleEndian>()?;
+        let mut key = vec![0; key_len as usize];
+        reader.read_exact(&mut key)?;
+        // Only single-file stores have hints
+        entries.push((
+            key,
+            Location {
+                segment: 0,
+                offset,
+                len,
+                expires_at: (expires_at != 0).then_some(expires_at),
+            },
+        ));
+    }
+
+    Ok(Some(Hint {
+        data_len,
+        fingerprint,
+        entries,
+    }))
+}
@@ -1,8 +1,14 @@
 use std::{
-    collections::HashMap,
+    collections::{btree_map, BTreeMap},
     fs::{File, OpenOptions},
     io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
-    path::Path,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use byteorder::ReadBytesExt;
@@ -10,181 +16,2127 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use crc::{Crc, CRC_32_ISO_HDLC};
 use serde::{Deserialize, Serialize};
 
+use super::bloom::{self, BloomFilter};
+use super::hint;
+use super::lock::{self, LockMode};
+use super::segment::{self, Compaction, Files};
+use super::{Change, Codec, Compression, KvError, KvOptions, Op, Snapshot, SyncPolicy, WriteBatch};
+
 type ByteString = Vec<u8>;
 type ByteStr = [u8];
 const CRC_32_ISO_HDLC_CALC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
+pub(crate) fn checksum(data: &[u8]) -> u32 {
+    CRC_32_ISO_HDLC_CALC.checksum(data)
+}
+
+/// Magic bytes at the start of every versioned store file.
+/// Files written before the header was introduced start directly
+/// with their first record; they are read as format version 0.
+const FILE_MAGIC: &[u8; 8] = b"ACTIONKV";
+
+/// The format version written to new files.
+/// Version 2 added the codec byte to the header; records are the same as in version 1.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Length of the version 1 header: magic bytes followed by a u32 version
+const HEADER_V1_LEN: u64 = FILE_MAGIC.len() as u64 + 4;
+
+/// Length of the current header: the version 1 header, then the codec byte
+/// and 3 reserved bytes
+pub(crate) const HEADER_LEN: u64 = HEADER_V1_LEN + 4;
+
+/// Set in the kind byte of a record whose value is LZ4-compressed
+const COMPRESSED_FLAG: u8 = 0x80;
+
+/// Set in the kind byte of a record with an expiry time.
+/// The time follows the lengths in the record header, as a u64 number of
+/// milliseconds since the Unix epoch.
+const EXPIRES_FLAG: u8 = 0x40;
+
+/// Length of a record header: checksum, kind byte and the two lengths.
+/// Records written before the file header have no kind byte.
+const RECORD_HEADER_LEN: usize = 13;
+
+/// Bytes read at a time when looking for an intact record after a damaged one
+const RESYNC_WINDOW: u64 = 64 * 1024;
+
+/// Bytes at each end of the data covered by a hint that its fingerprint checks
+const FINGERPRINT_LEN: u64 = 4096;
+
+/// The current time, as stored in expiring records
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyValuePair {
     pub key: ByteString,
     pub value: ByteString,
 }
 
+/// Where a record is stored in the backing files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// Id of the segment file holding the record. Always 0 for a single-file store.
+    pub segment: u64,
+
+    /// Number of bytes from the start of the file to the record
+    pub offset: u64,
+
+    /// Length of the whole record, including its header
+    pub len: u64,
+
+    /// When the key expires, in milliseconds since the Unix epoch.
+    /// `None` for keys that never expire.
+    pub expires_at: Option<u64>,
+}
+
+impl Location {
+    /// Whether the key has expired at `now`, in milliseconds since the Unix epoch
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+/// A point in the log: an offset within a segment.
+/// Positions order like the records they point to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// Always 0 for a single-file store
+    pub segment: u64,
+    pub offset: u64,
+}
+
+/// The type of a record in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// Sets a key to a value
+    Put = 0,
+
+    /// Marks a key as deleted
+    Tombstone = 1,
+
+    /// Starts a write batch. The value holds the number of records in the batch.
+    BatchBegin = 2,
+
+    /// Commits the write batch started by the matching `BatchBegin`.
+    /// The value repeats the number of records in the batch.
+    BatchCommit = 3,
+}
+
+impl RecordKind {
+    fn from_u8(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(RecordKind::Put),
+            1 => Ok(RecordKind::Tombstone),
+            2 => Ok(RecordKind::BatchBegin),
+            3 => Ok(RecordKind::BatchCommit),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown record kind {}", byte),
+            )),
+        }
+    }
+}
+
+/// Space used by a store, from `ActionKv::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Number of live keys
+    pub keys: usize,
+
+    /// Size of the backing file, including stale records
+    pub file_bytes: u64,
+
+    /// Bytes taken on disk by the live records, headers included
+    pub live_bytes: u64,
+
+    /// Size of the live keys and values, once decompressed
+    pub logical_bytes: u64,
+
+    /// How the Bloom filters are doing, if the store keeps them
+    pub bloom: Option<BloomStats>,
+}
+
+/// Bloom filters of a store, from `ActionKv::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BloomStats {
+    /// Number of filters: one per segment
+    pub filters: usize,
+
+    /// Total size of the filters
+    pub bits: u64,
+
+    /// Probability that `may_contain` is true for a key that was never
+    /// written, estimated from how full the filters are
+    pub estimated_false_positive_rate: f64,
+
+    /// Calls to `may_contain` answered with `false`
+    pub negatives: u64,
+
+    /// Calls to `may_contain` answered with `true` for a key that
+    /// isn't in the index. These include keys deleted or expired since the
+    /// last compaction, which the filters still hold.
+    pub false_positives: u64,
+}
+
+impl BloomStats {
+    /// Share of the lookups of absent keys that the filters failed to rule
+    /// out, or `None` before any such lookup
+    pub fn observed_false_positive_rate(&self) -> Option<f64> {
+        let absent = self.negatives + self.false_positives;
+        (absent > 0).then(|| self.false_positives as f64 / absent as f64)
+    }
+}
+
+/// Outcomes of `ActionKv::may_contain`, counted through a shared reference
+#[derive(Debug, Default)]
+struct BloomLookups {
+    negatives: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+/// Result of checking every record of a store with `ActionKv::verify`
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// Number of records found in the files, including damaged ones
+    pub records: u64,
+
+    /// One error per damaged record, in log order, with the id of the segment
+    /// it was found in
+    pub errors: Vec<(u64, KvError)>,
+}
+
+/// One version of a key, from `ActionKv::history`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Where the record is in the log
+    pub position: Position,
+
+    /// The value written, or `None` if the key was deleted
+    pub value: Option<ByteString>,
+
+    /// When this value expires, in milliseconds since the Unix epoch
+    pub expires_at: Option<u64>,
+}
+
+/// How far `ActionKv::replay_file` got
+struct Replayed {
+    /// End of the last committed record read
+    committed: u64,
+
+    /// Start of a write batch whose commit record wasn't found
+    uncommitted_batch: Option<u64>,
+}
+
+/// A write batch read from the log whose commit record hasn't been seen yet
+struct PendingBatch {
+    /// Offset of the `BatchBegin` record
+    start: u64,
+
+    /// Number of records announced by `BatchBegin`
+    count: u32,
+
+    records: Vec<(Record, Location)>,
+}
+
+/// A record as decoded from the log
+#[derive(Debug)]
+pub(crate) struct Record {
+    pub kind: RecordKind,
+    pub key: ByteString,
+    pub value: ByteString,
+
+    /// Length of the record as stored, header included
+    pub len: u64,
+
+    /// Expiry time of a put, in milliseconds since the Unix epoch
+    pub expires_at: Option<u64>,
+}
+
 #[derive(Debug)]
 pub struct ActionKv {
-    /// The file backing up the in-memory store
+    /// The file backing up the in-memory store.
+    /// For a segmented store, the active segment: the one appended to.
     f: File,
 
-    /// Maintains a mapping between keys and file locations
-    pub index: HashMap<ByteString, u64>,
+    /// Id of the segment in `f`. Always 0 for a single-file store.
+    segment: u64,
+
+    /// Read handles on the closed segments of a segmented store, by id
+    closed: BTreeMap<u64, File>,
+
+    /// Path of the backing file, needed to swap in a compacted file.
+    /// For a segmented store, the directory of the segments.
+    path: PathBuf,
+
+    /// Whether `path` is a directory of segments
+    segmented: bool,
+
+    /// The locked directory handle of a segmented store.
+    /// A single-file store locks `f` instead.
+    _dir_lock: Option<File>,
+
+    /// Compaction of closed segments running in the background
+    compaction: Option<Compaction>,
+
+    /// On-disk format version of the backing file.
+    /// 0 is the original headerless format.
+    version: u32,
+
+    /// Codec of the keys and values, from the file header
+    codec: Option<Codec>,
+
+    /// Maintains a mapping between keys and file locations, in key order
+    pub index: BTreeMap<ByteString, Location>,
+
+    /// Whether `index` was populated from the log by `load` or `recover`.
+    /// Until then it only holds the keys written since opening, so it must
+    /// not be saved as a hint or used to compact.
+    loaded: bool,
+
+    /// Offset of a write batch at the end of the log that was never committed,
+    /// e.g. because of a crash. It is cut off before anything else is appended,
+    /// so that later records aren't mistaken for part of it.
+    uncommitted_batch: Option<u64>,
+
+    options: KvOptions,
+
+    /// Writes appended since the file was last synced
+    unsynced_writes: usize,
+
+    last_sync: Instant,
+
+    /// Times `sync` forced the file to stable storage, see `syncs`
+    syncs: u64,
+
+    /// Channels of the subscribers to changes, see `subscribe`
+    subscribers: Vec<Sender<Change>>,
+
+    /// Bloom filter of the keys put in each segment, if `options.bloom_filter` is set
+    blooms: BTreeMap<u64, BloomFilter>,
+
+    bloom_lookups: BloomLookups,
 }
 
 impl ActionKv {
     /// Open a Key-Value store given its file path
-    pub fn open(path: &Path) -> io::Result<Self> {
-        let f = OpenOptions::new()
+    /// A new file is created with the current format header.
+    pub fn open(path: &Path) -> Result<Self, KvError> {
+        ActionKv::open_with(path, KvOptions::default())
+    }
+
+    /// Open a Key-Value store with non-default options
+    /// The file is locked while the store is open: exclusively, or shared
+    /// with other readers if `options.read_only` is set.
+    /// Fails with `KvError::Locked` if the lock is held elsewhere.
+    /// Windows has no locking yet: there, nothing stops two writers.
+    pub fn open_with(path: &Path, options: KvOptions) -> Result<Self, KvError> {
+        if options.segment_size.is_some() || path.is_dir() {
+            return ActionKv::open_dir(path, options);
+        }
+
+        let mut f = ActionKv::open_file(path, options.read_only)?;
+        ActionKv::lock(&f, path, &options)?;
+        let (version, codec) = ActionKv::read_or_init_header(&mut f, &options)?;
+
+        Ok(ActionKv::with_files(
+            f,
+            BTreeMap::new(),
+            path,
+            None,
+            version,
+            codec,
+            options,
+        ))
+    }
+
+    /// Opens a store kept as a directory of segment files, creating the
+    /// directory and its first segment if needed.
+    /// The directory itself is locked, since the active segment changes.
+    /// The segment size is saved in the directory when it is given, and read
+    /// from there when it isn't.
+    fn open_dir(path: &Path, mut options: KvOptions) -> Result<Self, KvError> {
+        if !options.read_only {
+            std::fs::create_dir_all(path)?;
+        }
+        let dir_lock = File::open(path)?;
+        ActionKv::lock(&dir_lock, path, &options)?;
+        if !options.read_only {
+            segment::finish_interrupted_compactions(path)?;
+        } else if segment::has_interrupted_install(path)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "A compaction was interrupted; open the store for writing to finish it",
+            )
+            .into());
+        }
+
+        let saved_size = segment::read_segment_size(path)?;
+        match options.segment_size {
+            Some(size) if !options.read_only && saved_size != Some(size) => {
+                segment::save_segment_size(path, size)?
+            }
+            None => options.segment_size = saved_size,
+            _ => {}
+        }
+
+        let mut ids = segment::list_segments(path)?;
+        let active_id = ids.pop().unwrap_or(0);
+        let mut f =
+            ActionKv::open_file(&segment::segment_path(path, active_id), options.read_only)?;
+        let (version, codec) = ActionKv::read_or_init_header(&mut f, &options)?;
+
+        let mut closed = BTreeMap::new();
+        for id in ids {
+            let mut segment = File::open(segment::segment_path(path, id))?;
+            if ActionKv::read_or_init_header(&mut segment, &options)? != (version, codec) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Segment {} has a different format or codec", id),
+                )
+                .into());
+            }
+            closed.insert(id, segment);
+        }
+
+        let mut store =
+            ActionKv::with_files(f, closed, path, Some(dir_lock), version, codec, options);
+        store.segment = active_id;
+        store.segmented = true;
+        Ok(store)
+    }
+
+    fn lock(f: &File, path: &Path, options: &KvOptions) -> Result<(), KvError> {
+        let mode = if options.read_only {
+            LockMode::Shared
+        } else {
+            LockMode::Exclusive
+        };
+        if !lock::try_lock(f, mode)? {
+            return Err(KvError::Locked {
+                path: path.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+
+    fn with_files(
+        f: File,
+        closed: BTreeMap<u64, File>,
+        path: &Path,
+        dir_lock: Option<File>,
+        version: u32,
+        codec: Option<Codec>,
+        options: KvOptions,
+    ) -> Self {
+        ActionKv {
+            f,
+            segment: 0,
+            closed,
+            path: path.to_path_buf(),
+            segmented: false,
+            _dir_lock: dir_lock,
+            compaction: None,
+            version,
+            codec,
+            index: BTreeMap::new(),
+            loaded: false,
+            uncommitted_batch: None,
+            options,
+            unsynced_writes: 0,
+            last_sync: Instant::now(),
+            syncs: 0,
+            subscribers: Vec::new(),
+            blooms: BTreeMap::new(),
+            bloom_lookups: BloomLookups::default(),
+        }
+    }
+
+    /// Whether the store is a directory of segment files rather than a single file
+    pub fn is_segmented(&self) -> bool {
+        self.segmented
+    }
+
+    /// Id of the segment new records are appended to.
+    /// Always 0 for a single-file store.
+    pub fn active_segment(&self) -> u64 {
+        self.segment
+    }
+
+    /// Ids of all the segments of the store, oldest first
+    pub fn segments(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.closed.keys().copied().collect();
+        ids.push(self.segment);
+        ids
+    }
+
+    fn files(&self) -> Files<'_> {
+        Files {
+            segment: self.segment,
+            active: &self.f,
+            closed: &self.closed,
+        }
+    }
+
+    fn open_file(path: &Path, read_only: bool) -> io::Result<File> {
+        if read_only {
+            return File::open(path);
+        }
+
+        OpenOptions::new()
             .read(true)
-            .write(true)
             .create(true)
             .append(true)
-            .open(path)?;
+            .open(path)
+    }
+
+    /// Returns the format version and codec of a file, writing a fresh header
+    /// if the file is empty and writable.
+    pub(crate) fn read_or_init_header(
+        f: &mut File,
+        options: &KvOptions,
+    ) -> io::Result<(u32, Option<Codec>)> {
+        let mut file_len = f.metadata()?.len();
+
+        // A crash while the file was being created can leave part of the header
+        if !options.read_only && ActionKv::is_torn_header(f, file_len)? {
+            f.set_len(0)?;
+            file_len = 0;
+        }
 
-        let index = HashMap::new();
+        if file_len == 0 {
+            if !options.read_only {
+                // Written in one go, so that a crash is unlikely to tear it
+                let mut header = Vec::with_capacity(HEADER_LEN as usize);
+                ActionKv::write_header(&mut header, options.codec)?;
+                f.write_all(&header)?;
+            }
+            return Ok((FORMAT_VERSION, options.codec));
+        }
+
+        if file_len < HEADER_V1_LEN {
+            return Ok((0, None));
+        }
+
+        let mut magic = [0u8; FILE_MAGIC.len()];
+        f.seek(SeekFrom::Start(0))?;
+        f.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Ok((0, None));
+        }
+
+        let version = f.read_u32::<LittleEndian>()?;
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported format version {}", version),
+            ));
+        }
+        if version == 1 {
+            return Ok((version, None));
+        }
+
+        let mut codec = [0u8; 4];
+        f.read_exact(&mut codec)?;
+        let codec = Codec::from_header_byte(codec[0]).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown codec {}", codec[0]),
+            )
+        })?;
+
+        Ok((version, codec))
+    }
+
+    /// Whether a file holds nothing but the start of a current header.
+    /// Version 1 files with no records are a complete header of their own.
+    fn is_torn_header(f: &mut File, file_len: u64) -> io::Result<bool> {
+        if file_len == 0 || file_len >= HEADER_LEN {
+            return Ok(false);
+        }
+        let mut start = Vec::with_capacity(HEADER_V1_LEN as usize);
+        start.extend_from_slice(FILE_MAGIC);
+        start.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+        let mut data = vec![0u8; file_len as usize];
+        f.seek(SeekFrom::Start(0))?;
+        f.read_exact(&mut data)?;
+        let compared = data.len().min(start.len());
+        Ok(data[..compared] == start[..compared])
+    }
+
+    pub(crate) fn write_header<W: Write>(writer: &mut W, codec: Option<Codec>) -> io::Result<()> {
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_u32::<LittleEndian>(FORMAT_VERSION)?;
+        writer.write_all(&[Codec::to_header_byte(codec), 0, 0, 0])?;
+        Ok(())
+    }
+
+    /// Offset of the first record in the backing file
+    fn data_start(&self) -> u64 {
+        ActionKv::data_start_of(self.version)
+    }
+
+    /// Offset of the first record in a file of the given format version
+    pub(crate) fn data_start_of(version: u32) -> u64 {
+        match version {
+            0 => 0,
+            1 => HEADER_V1_LEN,
+            _ => HEADER_LEN,
+        }
+    }
 
-        Ok(ActionKv { f, index })
+    /// Codec of the keys and values, as recorded when the store was created.
+    /// `None` for stores of raw bytes, including those written before codecs
+    /// were recorded.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
     }
 
     /// Populates the index mapping
-    pub fn load(&mut self) -> io::Result<()> {
+    /// If a hint file written by `close` or `compact` matches the data file,
+    /// the index is read from it and only the records appended since are scanned.
+    /// Segmented stores have no hint file: every segment is scanned.
+    /// Fails with `KvError::Corruption` or `KvError::TornRecord` if a record
+    /// cannot be decoded; use `recover` to repair a log with a torn tail.
+    /// `load` has no output.
+    pub fn load(&mut self) -> Result<(), KvError> {
+        self.load_index(false)?;
+        Ok(())
+    }
+
+    /// Populates the index mapping like `load`, but truncates the file
+    /// at a damaged record found at the end of the log, which is what an
+    /// interrupted write leaves behind.
+    /// Returns the offset the file was truncated at, if any.
+    /// In a segmented store, only the active segment can have a damaged tail.
+    /// Damage before the last record is still reported as an error,
+    /// since truncating there would discard valid records.
+    pub fn recover(&mut self) -> Result<Option<u64>, KvError> {
+        self.check_writable()?;
+        self.load_index(true)
+    }
+
+    fn load_index(&mut self, truncate_torn_tail: bool) -> Result<Option<u64>, KvError> {
+        let data_start = self.data_start();
+        let file_len = self.f.metadata()?.len();
+        let mut scan_start = data_start;
+
+        self.finish_compaction(true)?;
+        self.index.clear();
+
+        // Closed segments were complete when the next one was started:
+        // they can't have a torn tail or an uncommitted batch
+        for (&id, f) in &self.closed {
+            let index = &mut self.index;
+            let end = ActionKv::replay_file(f, id, self.version, data_start, u64::MAX, |r, l| {
+                ActionKv::apply(index, r, l)
+            })?;
+            if let Some(batch_start) = end.uncommitted_batch {
+                return Err(ActionKv::invalid_batch(batch_start));
+            }
+        }
+
+        // While repairing, the hint can't be trusted: read every record
+        if !truncate_torn_tail && !self.segmented {
+            if let Some(hint) = hint::read_hint(&hint::hint_path(&self.path), self.version)? {
+                let consistent = hint.data_len >= data_start
+                    && hint.data_len <= file_len
+                    && ActionKv::fingerprint(&self.f, hint.data_len)? == hint.fingerprint
+                    && hint
+                        .entries
+                        .iter()
+                        .all(|(_, location)| location.offset + location.len <= hint.data_len);
+                if consistent {
+                    self.index.extend(hint.entries);
+                    scan_start = hint.data_len;
+                }
+            }
+        }
+
         let mut bufreader = BufReader::new(&mut self.f);
+        bufreader.seek(SeekFrom::Start(scan_start))?;
+
+        // Records of a write batch are only applied once its commit record is read
+        let mut batch: Option<PendingBatch> = None;
+        self.uncommitted_batch = None;
 
         loop {
             // position is the number of bytes from the start of the file to the current location.
             // We will store {key, position} in the index mapping.
             let position = bufreader.stream_position()?;
-            let kv = match ActionKv::process_record(&mut bufreader) {
-                Ok(kv) => {
-                    println!(
-                        "Loading {}, {}",
-                        String::from_utf8_lossy(&kv.key),
-                        String::from_utf8_lossy(&kv.value)
-                    );
-                    kv
-                }
-                Err(err) => match err.kind() {
-                    io::ErrorKind::UnexpectedEof => {
-                        break;
+            if position >= file_len {
+                break;
+            }
+
+            let record = match ActionKv::process_record(&mut bufreader, self.version, position) {
+                Ok(record) => record,
+                Err(err) => {
+                    if !truncate_torn_tail {
+                        return Err(err);
                     }
-                    _ => {
+
+                    let at_tail = match err {
+                        // The stored length of a damaged record can point past the end of
+                        // the file; only treat it as torn if nothing valid follows it.
+                        KvError::TornRecord { .. } => {
+                            ActionKv::find_record_after(&mut bufreader, position, self.version)?
+                                .is_none()
+                        }
+                        KvError::Corruption { .. } => bufreader.stream_position()? >= file_len,
+                        _ => false,
+                    };
+                    if !at_tail {
                         return Err(err);
                     }
+
+                    drop(bufreader);
+                    self.truncate_to(position)?;
+                    self.uncommitted_batch = batch.map(|batch| batch.start);
+                    self.drop_expired();
+                    self.load_blooms()?;
+                    self.loaded = true;
+                    return Ok(Some(position));
+                }
+            };
+
+            let location = Location {
+                segment: self.segment,
+                offset: position,
+                len: record.len,
+                expires_at: record.expires_at,
+            };
+            match record.kind {
+                RecordKind::Put | RecordKind::Tombstone => match batch.as_mut() {
+                    Some(batch) => batch.records.push((record, location)),
+                    None => ActionKv::apply(&mut self.index, record, location),
                 },
+                RecordKind::BatchBegin => {
+                    // A batch can't start inside another one: uncommitted batches
+                    // are cut off before anything else is written
+                    if batch.is_some() {
+                        return Err(ActionKv::invalid_batch(position));
+                    }
+                    batch = Some(PendingBatch {
+                        start: position,
+                        count: ActionKv::batch_count(&record.value, position)?,
+                        records: Vec::new(),
+                    });
+                }
+                RecordKind::BatchCommit => {
+                    let count = ActionKv::batch_count(&record.value, position)?;
+                    match batch.take() {
+                        Some(batch)
+                            if batch.count == count && batch.records.len() == count as usize =>
+                        {
+                            for (record, location) in batch.records {
+                                ActionKv::apply(&mut self.index, record, location);
+                            }
+                        }
+                        _ => return Err(ActionKv::invalid_batch(position)),
+                    }
+                }
+            }
+        }
+
+        self.uncommitted_batch = batch.map(|batch| batch.start);
+        self.drop_expired();
+        self.load_blooms()?;
+        self.loaded = true;
+
+        Ok(None)
+    }
+
+    /// Sets up the Bloom filter of every segment, reading the saved one
+    /// if it still covers the whole segment, and otherwise building it from
+    /// the keys the index places in the segment
+    fn load_blooms(&mut self) -> Result<(), KvError> {
+        self.blooms.clear();
+        if self.options.bloom_filter.is_none() {
+            return Ok(());
+        }
+        let files = self.files();
+        let mut blooms = BTreeMap::new();
+        for segment in self.segments() {
+            let data_len = files.get(segment)?.metadata()?.len();
+            let saved = bloom::read_filter(&bloom::bloom_path(&self.data_path(segment)))?;
+            let filter = match saved {
+                Some((covered, filter)) if covered == data_len => filter,
+                _ => self.build_bloom(segment, 0),
             };
+            blooms.insert(segment, filter);
+        }
+        self.blooms = blooms;
+        Ok(())
+    }
+
+    /// A Bloom filter of the keys the index places in a segment,
+    /// sized for at least `capacity` keys
+    fn build_bloom(&self, segment: u64, capacity: u64) -> BloomFilter {
+        let keys: Vec<&ByteString> = self
+            .index
+            .iter()
+            .filter(|(_, location)| location.segment == segment)
+            .map(|(key, _)| key)
+            .collect();
+        let rate = self.options.bloom_filter.unwrap_or_default();
+        let mut filter = BloomFilter::new(capacity.max(keys.len() as u64), rate);
+        for key in keys {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    /// Adds a key just put in a segment to its Bloom filter.
+    /// A full filter is rebuilt at twice its size first.
+    fn add_to_bloom(&mut self, key: &ByteStr, segment: u64) {
+        let capacity = match self.blooms.get(&segment) {
+            Some(filter) if filter.is_full() => filter.capacity() * 2,
+            Some(_) => 0,
+            None => return,
+        };
+        if capacity > 0 {
+            let filter = self.build_bloom(segment, capacity);
+            self.blooms.insert(segment, filter);
+        }
+        self.blooms.get_mut(&segment).unwrap().insert(key);
+    }
+
+    /// Saves the Bloom filter of a segment next to it
+    fn save_bloom(&self, segment: u64) -> Result<(), KvError> {
+        let filter = match self.blooms.get(&segment) {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+        let data_len = self.files().get(segment)?.metadata()?.len();
+        let path = bloom::bloom_path(&self.data_path(segment));
+        bloom::write_filter(&path, data_len, filter)?;
+        Ok(())
+    }
+
+    /// Path of the file holding a segment
+    fn data_path(&self, segment: u64) -> PathBuf {
+        if self.segmented {
+            segment::segment_path(&self.path, segment)
+        } else {
+            self.path.clone()
+        }
+    }
+
+    /// Whether the store may hold a key, according to the Bloom filters.
+    /// `false` means the key was never written, or not since the last
+    /// compaction; `true` means it may have been.
+    /// Keys deleted or expired since the last compaction are still in the
+    /// filters, so they are answered with `true` and counted as false positives.
+    /// Always `true` if the store doesn't keep Bloom filters, or before `load`
+    /// has set them up.
+    pub fn may_contain(&self, key: &ByteStr) -> bool {
+        if self.options.bloom_filter.is_none() || !self.loaded {
+            return true;
+        }
+        let maybe = self.blooms.values().any(|filter| filter.may_contain(key));
+        let counter = if !maybe {
+            &self.bloom_lookups.negatives
+        } else if !self.index.contains_key(key) {
+            &self.bloom_lookups.false_positives
+        } else {
+            return true;
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        maybe
+    }
+
+    /// Removes the keys that have expired from the index.
+    /// Their records stay in the log until the next compaction.
+    fn drop_expired(&mut self) {
+        let now = now_millis();
+        self.index.retain(|_, location| !location.is_expired(now));
+    }
+
+    /// Applies a put or tombstone record to the index
+    fn apply(index: &mut BTreeMap<ByteString, Location>, record: Record, location: Location) {
+        match record.kind {
+            RecordKind::Put => {
+                index.insert(record.key, location);
+            }
+            RecordKind::Tombstone => {
+                index.remove(&record.key);
+            }
+            RecordKind::BatchBegin | RecordKind::BatchCommit => {}
+        }
+    }
+
+    /// Decodes the record count stored in batch begin and commit records
+    pub(crate) fn batch_count(value: &ByteStr, offset: u64) -> Result<u32, KvError> {
+        value
+            .try_into()
+            .map(u32::from_le_bytes)
+            .map_err(|_| ActionKv::invalid_batch(offset))
+    }
 
-            self.index.insert(kv.key, position);
+    pub(crate) fn invalid_batch(offset: u64) -> KvError {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Malformed write batch at offset {}", offset),
+        )
+        .into()
+    }
+
+    /// Cuts the log off at `position`, discarding everything after it
+    fn truncate_to(&mut self, position: u64) -> Result<(), KvError> {
+        self.f.set_len(position)?;
+        self.f.sync_data()?;
+        bloom::remove_filter(&self.data_path(self.segment))?;
+        // New records may later be appended past the truncation point,
+        // which would make an old hint look consistent again
+        self.remove_hint()
+    }
+
+    /// Looks for the first intact record starting after `position`, trying every
+    /// offset up to the end of the file.
+    /// The file is read a window at a time. Only offsets whose header is
+    /// plausible, with a known kind and lengths that fit in the file, are
+    /// checked further, so that a long damaged stretch is scanned in linear time.
+    fn find_record_after<R: Read + Seek>(
+        reader: &mut R,
+        position: u64,
+        version: u32,
+    ) -> Result<Option<u64>, KvError> {
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut window = Vec::new();
+        let mut window_start = position + 1;
+        while window_start < end {
+            // The window overlaps the next one by a header, so that every
+            // candidate's header is in the window it starts in
+            let window_end = end.min(window_start + RESYNC_WINDOW + RECORD_HEADER_LEN as u64);
+            window.resize((window_end - window_start) as usize, 0);
+            reader.seek(SeekFrom::Start(window_start))?;
+            reader.read_exact(&mut window)?;
+
+            let candidates = RESYNC_WINDOW.min(end - window_start) as usize;
+            for i in 0..candidates {
+                let offset = window_start + i as u64;
+                let len = match ActionKv::claimed_len(&window[i..], version) {
+                    Some(len) if len <= end - offset => len,
+                    _ => continue,
+                };
+                let intact = if i as u64 + len <= window.len() as u64 {
+                    let mut record = &window[i..i + len as usize];
+                    ActionKv::process_record(&mut record, version, offset).is_ok()
+                } else {
+                    reader.seek(SeekFrom::Start(offset))?;
+                    ActionKv::process_record(reader, version, offset).is_ok()
+                };
+                if intact {
+                    return Ok(Some(offset));
+                }
+            }
+            window_start += RESYNC_WINDOW;
         }
 
-        println!("Index map:");
-        for (k, i) in self.index.iter() {
-            println!("\t{} -> {}", String::from_utf8_lossy(k), i);
+        Ok(None)
+    }
+
+    /// Length of the record whose header starts `data`, if it could be a
+    /// record header: it is all there and its kind is known.
+    fn claimed_len(data: &[u8], version: u32) -> Option<u64> {
+        let (lengths, extra) = if version == 0 {
+            (data.get(4..12)?, 0)
+        } else {
+            let kind = *data.get(4)?;
+            RecordKind::from_u8(kind & !(COMPRESSED_FLAG | EXPIRES_FLAG)).ok()?;
+            let expiry = if kind & EXPIRES_FLAG != 0 { 8 } else { 0 };
+            (data.get(5..RECORD_HEADER_LEN)?, 1 + expiry)
+        };
+        let key_len = u32::from_le_bytes(lengths[0..4].try_into().unwrap()) as u64;
+        let val_len = u32::from_le_bytes(lengths[4..8].try_into().unwrap()) as u64;
+        Some(4 + 8 + extra + key_len + val_len)
+    }
+
+    /// Checks every record in the files without modifying them or the index.
+    /// A record with a bad checksum is skipped using its stored lengths,
+    /// so that the records after it can still be checked.
+    pub fn verify(&mut self) -> Result<VerifyReport, KvError> {
+        let mut report = VerifyReport {
+            records: 0,
+            errors: Vec::new(),
+        };
+        let files = self.files();
+        for segment in self.segments() {
+            let f = files.get(segment)?;
+            ActionKv::verify_file(f, segment, self.version, self.data_start(), &mut report)?;
+        }
+        Ok(report)
+    }
+
+    fn verify_file(
+        mut f: &File,
+        segment: u64,
+        version: u32,
+        data_start: u64,
+        report: &mut VerifyReport,
+    ) -> Result<(), KvError> {
+        let file_len = f.metadata()?.len();
+        let mut bufreader = BufReader::new(&mut f);
+        bufreader.seek(SeekFrom::Start(data_start))?;
+
+        loop {
+            let position = bufreader.stream_position()?;
+            if position >= file_len {
+                break;
+            }
+
+            report.records += 1;
+            match ActionKv::process_record(&mut bufreader, version, position) {
+                Ok(_) => {}
+                Err(err @ KvError::Corruption { .. }) => report.errors.push((segment, err)),
+                Err(err @ KvError::TornRecord { .. }) => {
+                    report.errors.push((segment, err));
+                    // The record's length may be what is damaged: resume at the next intact record
+                    match ActionKv::find_record_after(&mut bufreader, position, version)? {
+                        Some(next) => {
+                            bufreader.seek(SeekFrom::Start(next))?;
+                        }
+                        None => break,
+                    }
+                }
+                Err(err) => return Err(err),
+            }
         }
 
         Ok(())
     }
 
     /// Insert a (key/value) pair
-    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        let position = self.insert_but_ignore_index(key, value)?;
-        self.index.insert(key.to_vec(), position);
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<(), KvError> {
+        let location = self.append(RecordKind::Put, key, value, None)?;
+        self.index.insert(key.to_vec(), location);
+        self.publish(key, RecordKind::Put, value, &location);
         Ok(())
     }
 
-    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
-        let position = match self.index.get(key) {
-            None => return Ok(None),
-            Some(position) => *position,
+    /// Insert a (key/value) pair that expires after `ttl`.
+    /// Once expired, the key reads as absent; it is dropped from the index
+    /// by the next `load` and from the file by the next compaction.
+    pub fn insert_with_ttl(
+        &mut self,
+        key: &ByteStr,
+        value: &ByteStr,
+        ttl: Duration,
+    ) -> Result<(), KvError> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let expires_at = now_millis().saturating_add(ttl);
+        let location = self.append(RecordKind::Put, key, value, Some(expires_at))?;
+        self.index.insert(key.to_vec(), location);
+        self.publish(key, RecordKind::Put, value, &location);
+        Ok(())
+    }
+
+    /// Look up the value of a key.
+    /// Reads use positional I/O and don't move the file cursor,
+    /// so several threads can call this at once (see `SharedKv`).
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>, KvError> {
+        let location = match self.index.get(key) {
+            Some(location) if !location.is_expired(now_millis()) => location,
+            _ => return Ok(None),
         };
-        let kv = self.get_at(position)?;
+        let kv = self.get_at(location)?;
         Ok(Some(kv.value))
     }
 
+    /// Iterate over all live (key, value) pairs in key byte order
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(..)
+    }
+
+    /// Iterate over the live (key, value) pairs whose keys fall in `range`,
+    /// in key byte order
+    pub fn range<R>(&self, range: R) -> Iter<'_>
+    where
+        R: RangeBounds<ByteStr>,
+    {
+        Iter {
+            entries: self.index.range::<ByteStr, _>(range),
+            prefix: None,
+            files: self.files(),
+            version: self.version,
+            now: now_millis(),
+        }
+    }
+
+    /// Iterate over the live (key, value) pairs whose keys start with `prefix`,
+    /// in key byte order
+    pub fn scan_prefix(&self, prefix: &ByteStr) -> Iter<'_> {
+        Iter {
+            entries: self
+                .index
+                .range::<ByteStr, _>((Bound::Included(prefix), Bound::Unbounded)),
+            prefix: Some(prefix.to_vec()),
+            files: self.files(),
+            version: self.version,
+            now: now_millis(),
+        }
+    }
+
     /// Update a key with a value
     /// Because we're using an append-only design, to update a key, we
     /// simply write the new value at the end of the file.
-    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> Result<(), KvError> {
         self.insert(key, value)
     }
 
     /// Delete a key from the mapping
     /// Because we're using an append-only design, to delete a key,
-    /// we simply write an empty value.
-    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
-        self.insert(key, b"")
+    /// we write a tombstone record for it.
+    pub fn delete(&mut self, key: &ByteStr) -> Result<(), KvError> {
+        let location = self.append(RecordKind::Tombstone, key, b"", None)?;
+        self.index.remove(key);
+        self.publish(key, RecordKind::Tombstone, b"", &location);
+        Ok(())
     }
 
-    /// Read a record from the current location of a reader
-    fn process_record<R: Read>(reader: &mut R) -> io::Result<KeyValuePair> {
-        let saved_checksum = reader.read_u32::<LittleEndian>()?;
-        let key_len = reader.read_u32::<LittleEndian>()?;
-        let val_len = reader.read_u32::<LittleEndian>()?;
-        let data_len = key_len + val_len;
+    /// Read a record from the current location of a reader.
+    /// `version` is the format version of the file being read and `offset`
+    /// the position of the record, used to report errors.
+    fn process_record<R: Read>(
+        reader: &mut R,
+        version: u32,
+        offset: u64,
+    ) -> Result<Record, KvError> {
+        if version == 0 {
+            return ActionKv::process_legacy_record(reader, offset);
+        }
+
+        let mut header = [0u8; 13];
+        ActionKv::read_exact_at(reader, &mut header, offset)?;
+        let saved_checksum = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let header = &header[4..];
+        let kind = header[0];
+        let key_len = u32::from_le_bytes(header[1..5].try_into().unwrap());
+        let val_len = u32::from_le_bytes(header[5..9].try_into().unwrap());
+
+        let mut expiry = [0u8; 8];
+        let expiry: &[u8] = if kind & EXPIRES_FLAG != 0 {
+            ActionKv::read_exact_at(reader, &mut expiry, offset)?;
+            &expiry
+        } else {
+            &[]
+        };
 
-        let mut data = ByteString::with_capacity(data_len as usize);
+        let data = ActionKv::read_data(reader, key_len as u64 + val_len as u64, offset)?;
 
-        {
-            reader
-                .by_ref()
-                .take(data_len as u64)
-                .read_to_end(&mut data)?;
+        let mut digest = CRC_32_ISO_HDLC_CALC.digest();
+        digest.update(header);
+        digest.update(expiry);
+        digest.update(&data);
+        let checksum = digest.finalize();
+        if checksum != saved_checksum {
+            return Err(KvError::Corruption {
+                offset,
+                expected: saved_checksum,
+                actual: checksum,
+            });
         }
 
+        let compressed = kind & COMPRESSED_FLAG != 0;
+        let kind = RecordKind::from_u8(kind & !(COMPRESSED_FLAG | EXPIRES_FLAG))?;
+        let expires_at = expiry.try_into().ok().map(u64::from_le_bytes);
+        let (key, value) = data.split_at(key_len as usize);
+        let value = if compressed {
+            lz4_flex::decompress_size_prepended(value).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Bad compressed value at offset {}: {}", offset, err),
+                )
+            })?
+        } else {
+            value.to_vec()
+        };
+
+        Ok(Record {
+            kind,
+            key: key.to_vec(),
+            value,
+            len: (4 + header.len() + expiry.len() + data.len()) as u64,
+            expires_at,
+        })
+    }
+
+    /// Read a record written before the file header was introduced.
+    /// That format had no record kind: deletions were written as empty values,
+    /// so those are read back as tombstones.
+    fn process_legacy_record<R: Read>(reader: &mut R, offset: u64) -> Result<Record, KvError> {
+        let mut header = [0u8; 12];
+        ActionKv::read_exact_at(reader, &mut header, offset)?;
+        let saved_checksum = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let key_len = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let val_len = u32::from_le_bytes(header[8..12].try_into().unwrap());
+
+        let data = ActionKv::read_data(reader, key_len as u64 + val_len as u64, offset)?;
+
         let checksum = CRC_32_ISO_HDLC_CALC.checksum(&data);
         if checksum != saved_checksum {
-            panic!(
-                "Data corruption encounted ({:08x} != {:08x})",
-                checksum, saved_checksum
-            );
+            return Err(KvError::Corruption {
+                offset,
+                expected: saved_checksum,
+                actual: checksum,
+            });
         }
 
         let (key, value) = data.split_at(key_len as usize);
+        let kind = if value.is_empty() {
+            RecordKind::Tombstone
+        } else {
+            RecordKind::Put
+        };
 
-        Ok(KeyValuePair {
+        Ok(Record {
+            kind,
             key: key.to_vec(),
             value: value.to_vec(),
+            len: (header.len() + data.len()) as u64,
+            expires_at: None,
         })
     }
 
+    /// Fills `buf` from the reader, reporting a short read as a torn record at `offset`
+    fn read_exact_at<R: Read>(reader: &mut R, buf: &mut [u8], offset: u64) -> Result<(), KvError> {
+        reader.read_exact(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => KvError::TornRecord { offset },
+            _ => KvError::Io(err),
+        })
+    }
+
+    /// Reads exactly `data_len` bytes of the record at `offset`
+    fn read_data<R: Read>(
+        reader: &mut R,
+        data_len: u64,
+        offset: u64,
+    ) -> Result<ByteString, KvError> {
+        // Don't trust the length for the allocation: it may be corrupted
+        let mut data = ByteString::with_capacity(data_len.min(1 << 16) as usize);
+
+        reader.by_ref().take(data_len).read_to_end(&mut data)?;
+        if (data.len() as u64) < data_len {
+            return Err(KvError::TornRecord { offset });
+        }
+
+        Ok(data)
+    }
+
     /// Inserts (k, v) to the backing file
     /// Because we're using an append-only design,
     /// we will append the new value at the end of the file.
     /// In the file, there can be multiple (k, v) pairs, in which the last one
     /// is the updated version. Earlier pairs are stale values.
     /// Return the current position in the file, before writing (k, v)
-    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
-        let mut writer = BufWriter::new(&mut self.f);
-        let key_len = key.len();
-        let value_len = value.len();
-        let mut tmp = ByteString::with_capacity(key_len + value_len);
+    pub fn insert_but_ignore_index(
+        &mut self,
+        key: &ByteStr,
+        value: &ByteStr,
+    ) -> Result<u64, KvError> {
+        let location = self.append(RecordKind::Put, key, value, None)?;
+        self.publish(key, RecordKind::Put, value, &location);
+        Ok(location.offset)
+    }
 
-        for &byte in key {
-            tmp.push(byte);
+    /// Appends a record to the end of the backing file and returns its location.
+    /// Files in the headerless format cannot represent tombstones, so they are
+    /// read-only until they are upgraded with `compact`.
+    fn append(
+        &mut self,
+        kind: RecordKind,
+        key: &ByteStr,
+        value: &ByteStr,
+        expires_at: Option<u64>,
+    ) -> Result<Location, KvError> {
+        self.prepare_append()?;
+
+        // Serialize first so the record reaches the file in a single write
+        let mut buf = ByteString::new();
+        let len = ActionKv::write_record(
+            &mut buf,
+            kind,
+            key,
+            value,
+            expires_at,
+            self.options.compression,
+        )?;
+
+        // Write the new record (k, v) at the end of the file
+        let record_position = self.f.seek(SeekFrom::End(0))?;
+        self.f.write_all(&buf)?;
+        self.after_write()?;
+        if kind == RecordKind::Put {
+            self.add_to_bloom(key, self.segment);
         }
 
-        for &byte in value {
-            tmp.push(byte);
+        Ok(Location {
+            segment: self.segment,
+            offset: record_position,
+            len,
+            expires_at,
+        })
+    }
+
+    /// Syncs the file if the sync policy says a write makes it due
+    fn after_write(&mut self) -> Result<(), KvError> {
+        self.unsynced_writes += 1;
+        self.sync_if_due()
+    }
+
+    /// Syncs the file if there are unsynced writes and the sync policy says
+    /// they are due. The store has no thread of its own, so a
+    /// `SyncPolicy::Interval` is otherwise only checked on the next write:
+    /// a writer that goes quiet can call this from a timer instead.
+    pub fn sync_if_due(&mut self) -> Result<(), KvError> {
+        if self.unsynced_writes == 0 {
+            return Ok(());
+        }
+        let due = match self.options.sync {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::EveryN(n) => self.unsynced_writes >= n,
+            SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+        };
+        if due {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Hands any buffered writes to the operating system.
+    /// Records are written to the file as soon as they are appended,
+    /// so this only matters for writers that buffer.
+    pub fn flush(&mut self) -> Result<(), KvError> {
+        self.f.flush()?;
+        Ok(())
+    }
+
+    /// Forces all appended records to stable storage, whatever the sync policy
+    pub fn sync(&mut self) -> Result<(), KvError> {
+        self.flush()?;
+        self.f.sync_data()?;
+        self.syncs += 1;
+        self.unsynced_writes = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Number of times appended records were forced to stable storage,
+    /// by `sync` or by the sync policy, since the store was opened
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    /// Number of writes appended since the last sync
+    pub fn unsynced_writes(&self) -> usize {
+        self.unsynced_writes
+    }
+
+    /// Returns a channel receiving a `Change` for every put and delete made
+    /// through this handle from now on, once it has been appended to the log.
+    /// The changes of a write batch arrive together, after the whole batch
+    /// has been written. Compaction rewrites records but changes nothing,
+    /// so it isn't reported.
+    /// Dropping the receiver ends the subscription.
+    pub fn subscribe(&mut self) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Sends a change to every subscriber, forgetting those that have gone away
+    fn publish(&mut self, key: &ByteStr, kind: RecordKind, value: &ByteStr, location: &Location) {
+        if self.subscribers.is_empty() {
+            return;
         }
+        let op = match kind {
+            RecordKind::Tombstone => Op::Delete,
+            _ => Op::Put {
+                value: value.to_vec(),
+                expires_at: location.expires_at,
+            },
+        };
+        let change = Change {
+            key: key.to_vec(),
+            op,
+            segment: location.segment,
+            offset: location.offset,
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+    }
+
+    /// Checks that the file can be appended to, and discards an uncommitted
+    /// batch left at its end.
+    fn prepare_append(&mut self) -> Result<(), KvError> {
+        self.check_writable()?;
+        if self.version == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Store uses the legacy format; compact it to upgrade before writing",
+            )
+            .into());
+        }
+
+        if let Some(position) = self.uncommitted_batch.take() {
+            self.truncate_to(position)?;
+        }
+
+        self.finish_compaction(false)?;
+        self.maybe_roll_segment()
+    }
+
+    fn check_writable(&self) -> Result<(), KvError> {
+        if self.options.read_only {
+            return Err(KvError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Writes all the operations of a batch so that, after a crash, `load`
+    /// either sees all of them or none.
+    /// The records are framed by a begin record and a commit record, and the
+    /// whole batch is handed to the file in a single write.
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<(), KvError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.prepare_append()?;
+
+        let count = (batch.len() as u32).to_le_bytes();
+        let mut buf = ByteString::new();
+        ActionKv::write_record(
+            &mut buf,
+            RecordKind::BatchBegin,
+            b"",
+            &count,
+            None,
+            Compression::None,
+        )?;
+
+        let mut locations = Vec::with_capacity(batch.len());
+        for (kind, key, value) in &batch.ops {
+            let offset = buf.len() as u64;
+            let len = ActionKv::write_record(
+                &mut buf,
+                *kind,
+                key,
+                value,
+                None,
+                self.options.compression,
+            )?;
+            locations.push((offset, len));
+        }
+
+        ActionKv::write_record(
+            &mut buf,
+            RecordKind::BatchCommit,
+            b"",
+            &count,
+            None,
+            Compression::None,
+        )?;
+
+        let batch_position = self.f.seek(SeekFrom::End(0))?;
+        self.f.write_all(&buf)?;
+        self.after_write()?;
+
+        for ((kind, key, value), (offset, len)) in batch.ops.into_iter().zip(locations) {
+            let location = Location {
+                segment: self.segment,
+                offset: batch_position + offset,
+                len,
+                expires_at: None,
+            };
+            self.publish(&key, kind, &value, &location);
+            match kind {
+                RecordKind::Tombstone => {
+                    self.index.remove(&key);
+                }
+                _ => {
+                    self.add_to_bloom(&key, self.segment);
+                    self.index.insert(key, location);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes a record to a writer: checksum, kind, key length,
+    /// value length, the expiry time if there is one, then the key and value bytes.
+    /// The checksum covers everything that follows it.
+    /// The value of a put is compressed if `compression` asks for it and it
+    /// makes the value smaller.
+    /// Returns the number of bytes written.
+    pub(crate) fn write_record<W: Write>(
+        writer: &mut W,
+        kind: RecordKind,
+        key: &ByteStr,
+        value: &ByteStr,
+        expires_at: Option<u64>,
+        compression: Compression,
+    ) -> io::Result<u64> {
+        let mut kind = kind as u8;
+        if expires_at.is_some() {
+            kind |= EXPIRES_FLAG;
+        }
+        let compressed;
+        let mut value = value;
+        if compression == Compression::Lz4 && kind & !EXPIRES_FLAG == RecordKind::Put as u8 {
+            compressed = lz4_flex::compress_prepend_size(value);
+            if compressed.len() < value.len() {
+                value = &compressed;
+                kind |= COMPRESSED_FLAG;
+            }
+        }
+
+        let key_len = key.len();
+        let value_len = value.len();
+        let mut tmp = ByteString::with_capacity(17 + key_len + value_len);
+
+        tmp.push(kind);
+        tmp.write_u32::<LittleEndian>(key_len as u32)?;
+        tmp.write_u32::<LittleEndian>(value_len as u32)?;
+        if let Some(expires_at) = expires_at {
+            tmp.write_u64::<LittleEndian>(expires_at)?;
+        }
+        tmp.extend_from_slice(key);
+        tmp.extend_from_slice(value);
 
         let checksum = CRC_32_ISO_HDLC_CALC.checksum(&tmp);
 
-        // Write the new record (k, v) at the end of the file
-        let record_position = writer.seek(SeekFrom::End(0))?;
         writer.write_u32::<LittleEndian>(checksum)?;
-        writer.write_u32::<LittleEndian>(key_len as u32)?;
-        writer.write_u32::<LittleEndian>(value_len as u32)?;
         writer.write_all(&tmp)?;
 
-        Ok(record_position)
+        Ok(4 + tmp.len() as u64)
+    }
+
+    /// Rewrites the backing file so it only contains the live records
+    /// referenced by the index.
+    /// The live records are copied into a temporary file next to the store,
+    /// which is then renamed over the original, so a crash in the middle of
+    /// compaction leaves the original file untouched.
+    /// Stores in the legacy format are upgraded to the current format, and
+    /// values are rewritten with the compression chosen when opening the store.
+    /// A segmented store closes its active segment, if it holds any record,
+    /// and compacts all the segments into one, waiting for it to finish.
+    pub fn compact(&mut self) -> Result<(), KvError> {
+        self.check_writable()?;
+        self.ensure_loaded()?;
+
+        if self.segmented {
+            self.finish_compaction(true)?;
+            if let Some(position) = self.uncommitted_batch.take() {
+                self.truncate_to(position)?;
+            }
+            if self.f.metadata()?.len() > self.data_start() {
+                self.roll_segment()?;
+            }
+            self.start_compaction()?;
+            return self.finish_compaction(true);
+        }
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        // A leftover from an interrupted compaction: we hold the store's
+        // lock, so nobody else can be writing it
+        if let Err(err) = std::fs::remove_file(&tmp_path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+
+        // The new file becomes the store's file, so it is locked before it
+        // is renamed into place and kept open afterwards
+        let tmp_file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        if !lock::try_lock(&tmp_file, LockMode::Exclusive)? {
+            return Err(KvError::Locked { path: tmp_path });
+        }
+
+        let mut index = BTreeMap::new();
+        {
+            let mut writer = BufWriter::new(&tmp_file);
+            ActionKv::write_header(&mut writer, self.codec)?;
+            let mut position = HEADER_LEN;
+
+            // Expired keys are dropped rather than copied
+            let now = now_millis();
+            let mut locations: Vec<(&ByteString, &Location)> = self
+                .index
+                .iter()
+                .filter(|(_, location)| !location.is_expired(now))
+                .collect();
+            // Copy records in file order to keep reads of the old file sequential
+            locations.sort_by_key(|&(_, location)| location.offset);
+
+            for (key, old_location) in locations {
+                let record = ActionKv::read_record_at(&self.f, old_location.offset, self.version)?;
+                let len = ActionKv::write_record(
+                    &mut writer,
+                    RecordKind::Put,
+                    &record.key,
+                    &record.value,
+                    record.expires_at,
+                    self.options.compression,
+                )?;
+                index.insert(
+                    key.clone(),
+                    Location {
+                        segment: 0,
+                        offset: position,
+                        len,
+                        expires_at: record.expires_at,
+                    },
+                );
+                position += len;
+            }
+
+            writer.flush()?;
+        }
+        tmp_file.sync_all()?;
+
+        // The old hint and filter describe the old file: they must be gone
+        // before the swap
+        self.remove_hint()?;
+        bloom::remove_filter(&self.path)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.f = tmp_file;
+        self.version = FORMAT_VERSION;
+        self.index = index;
+        self.uncommitted_batch = None;
+        self.unsynced_writes = 0;
+        self.write_hint()?;
+        self.load_blooms()?;
+        self.save_bloom(0)?;
+
+        Ok(())
+    }
+
+    /// Takes a read-only view of the store as it is now.
+    /// The view is not affected by later writes, deletes or compactions.
+    pub fn snapshot(&self) -> Result<Snapshot, KvError> {
+        let end = match self.uncommitted_batch {
+            Some(position) => position,
+            None => self.f.metadata()?.len(),
+        };
+        self.snapshot_with(
+            Position {
+                segment: self.segment,
+                offset: end,
+            },
+            self.index.clone(),
+        )
+    }
+
+    /// Rebuilds the view of the store as it was when its log ended at `end`,
+    /// by reading every record before it.
+    /// A record that straddles `end`, or a write batch committed after it,
+    /// is not part of the view. Compaction discards the old versions this relies on.
+    /// Fails with `KvError::NoSuchPosition` if `end` is not in the log.
+    pub fn snapshot_at(&self, end: Position) -> Result<Snapshot, KvError> {
+        if !self.segments().contains(&end.segment) {
+            return Err(KvError::NoSuchPosition { position: end });
+        }
+        let len = self.files().get(end.segment)?.metadata()?.len();
+        if end.offset < self.data_start() || end.offset > len {
+            return Err(KvError::NoSuchPosition { position: end });
+        }
+
+        let mut index = BTreeMap::new();
+        let end = self.replay(end, |record, location| {
+            ActionKv::apply(&mut index, record, location)
+        })?;
+        self.snapshot_with(end, index)
+    }
+
+    fn snapshot_with(
+        &self,
+        end: Position,
+        index: BTreeMap<ByteString, Location>,
+    ) -> Result<Snapshot, KvError> {
+        let mut closed = BTreeMap::new();
+        for (&id, f) in &self.closed {
+            closed.insert(id, f.try_clone()?);
+        }
+        Ok(Snapshot {
+            f: self.f.try_clone()?,
+            segment: self.segment,
+            closed,
+            version: self.version,
+            end,
+            index,
+        })
+    }
+
+    /// Lists every committed version of a key still in the log, oldest first
+    pub fn history(&self, key: &ByteStr) -> Result<Vec<HistoryEntry>, KvError> {
+        let mut entries = Vec::new();
+        let end = Position {
+            segment: u64::MAX,
+            offset: u64::MAX,
+        };
+        self.replay(end, |record, location| {
+            if record.key != key {
+                return;
+            }
+            let value = match record.kind {
+                RecordKind::Put => Some(record.value),
+                _ => None,
+            };
+            entries.push(HistoryEntry {
+                position: Position {
+                    segment: location.segment,
+                    offset: location.offset,
+                },
+                value,
+                expires_at: record.expires_at,
+            });
+        })?;
+        Ok(entries)
+    }
+
+    /// Reads the segments in order, handing each committed put or tombstone
+    /// that ends by `end` to `visit`.
+    /// Returns the position where reading stopped.
+    fn replay<F>(&self, end: Position, mut visit: F) -> Result<Position, KvError>
+    where
+        F: FnMut(Record, Location),
+    {
+        let mut reached = Position {
+            segment: 0,
+            offset: self.data_start(),
+        };
+        let files = self.files();
+        for segment in self.segments() {
+            if segment > end.segment {
+                break;
+            }
+            let limit = if segment == end.segment {
+                end.offset
+            } else {
+                u64::MAX
+            };
+            let f = files.get(segment)?;
+            let replayed = ActionKv::replay_file(
+                f,
+                segment,
+                self.version,
+                self.data_start(),
+                limit,
+                &mut visit,
+            )?;
+            reached = Position {
+                segment,
+                offset: replayed.committed,
+            };
+        }
+        Ok(reached)
+    }
+
+    /// Reads the records of one file from `start`, handing each committed
+    /// put or tombstone that ends by `end` to `visit`, in file order.
+    fn replay_file<F>(
+        f: &File,
+        segment: u64,
+        version: u32,
+        start: u64,
+        end: u64,
+        mut visit: F,
+    ) -> Result<Replayed, KvError>
+    where
+        F: FnMut(Record, Location),
+    {
+        let end = end.min(f.metadata()?.len());
+        let mut reader = BufReader::new(PositionalReader { f, position: start });
+        let mut position = start;
+        let mut batch: Option<PendingBatch> = None;
+        let mut committed = position;
+
+        while position < end {
+            let record = ActionKv::process_record(&mut reader, version, position)?;
+            if position + record.len > end {
+                break;
+            }
+            let location = Location {
+                segment,
+                offset: position,
+                len: record.len,
+                expires_at: record.expires_at,
+            };
+            position += record.len;
+
+            match record.kind {
+                RecordKind::Put | RecordKind::Tombstone => match batch.as_mut() {
+                    Some(batch) => batch.records.push((record, location)),
+                    None => {
+                        visit(record, location);
+                        committed = position;
+                    }
+                },
+                RecordKind::BatchBegin => {
+                    if batch.is_some() {
+                        return Err(ActionKv::invalid_batch(location.offset));
+                    }
+                    batch = Some(PendingBatch {
+                        start: location.offset,
+                        count: ActionKv::batch_count(&record.value, location.offset)?,
+                        records: Vec::new(),
+                    });
+                }
+                RecordKind::BatchCommit => {
+                    let count = ActionKv::batch_count(&record.value, location.offset)?;
+                    match batch.take() {
+                        Some(batch)
+                            if batch.count == count && batch.records.len() == count as usize =>
+                        {
+                            for (record, location) in batch.records {
+                                visit(record, location);
+                            }
+                            committed = position;
+                        }
+                        _ => return Err(ActionKv::invalid_batch(location.offset)),
+                    }
+                }
+            }
+        }
+
+        Ok(Replayed {
+            committed,
+            uncommitted_batch: batch.map(|batch| batch.start),
+        })
+    }
+
+    /// Measures the space used by the live records against their logical size.
+    /// This reads every live value.
+    pub fn stats(&self) -> Result<Stats, KvError> {
+        let mut stats = Stats {
+            keys: self.index.len(),
+            file_bytes: self.f.metadata()?.len(),
+            ..Default::default()
+        };
+        for f in self.closed.values() {
+            stats.file_bytes += f.metadata()?.len();
+        }
+
+        let files = self.files();
+        for location in self.index.values() {
+            let record = files.read(location, self.version)?;
+            stats.live_bytes += location.len;
+            stats.logical_bytes += (record.key.len() + record.value.len()) as u64;
+        }
+
+        if self.options.bloom_filter.is_some() {
+            // A key is a false positive if any of the filters lets it through
+            let ruled_out: f64 = self
+                .blooms
+                .values()
+                .map(|filter| 1.0 - filter.false_positive_rate())
+                .product();
+            stats.bloom = Some(BloomStats {
+                filters: self.blooms.len(),
+                bits: self.blooms.values().map(BloomFilter::bits).sum(),
+                estimated_false_positive_rate: 1.0 - ruled_out,
+                negatives: self.bloom_lookups.negatives.load(Ordering::Relaxed),
+                false_positives: self.bloom_lookups.false_positives.load(Ordering::Relaxed),
+            });
+        }
+
+        Ok(stats)
+    }
+
+    /// Starts a new active segment if the current one has reached the
+    /// segment size, compacting the closed segments in the background if
+    /// there are enough of them.
+    fn maybe_roll_segment(&mut self) -> Result<(), KvError> {
+        let segment_size = match self.options.segment_size {
+            Some(size) if self.segmented => size,
+            _ => return Ok(()),
+        };
+        // A segment always gets at least one record, however small the size
+        let len = self.f.metadata()?.len();
+        if len < segment_size || len <= self.data_start() {
+            return Ok(());
+        }
+
+        self.roll_segment()?;
+        let threshold = self.options.compact_segments;
+        if threshold > 0 && self.closed.len() >= threshold {
+            self.start_compaction()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the active segment and starts a new, empty one
+    fn roll_segment(&mut self) -> Result<(), KvError> {
+        // Closed segments are never written again: make them durable now
+        self.sync()?;
+
+        let id = self.segment + 1;
+        let mut f = ActionKv::open_file(&segment::segment_path(&self.path, id), false)?;
+        ActionKv::write_header(&mut f, self.codec)?;
+        f.sync_data()?;
+
+        let old = std::mem::replace(&mut self.f, f);
+        self.closed.insert(self.segment, old);
+        self.save_bloom(self.segment)?;
+        if let Some(filter) = self.blooms.get(&self.segment) {
+            let capacity = filter.capacity();
+            self.blooms.insert(id, self.build_bloom(id, capacity));
+        }
+        self.segment = id;
+        Ok(())
+    }
+
+    /// Starts compacting every closed segment on a background thread.
+    /// Writes can continue meanwhile: they go to the active segment.
+    /// The compacted segment replaces the closed ones at the next write,
+    /// or when `compact`, `load` or `close` waits for it.
+    /// Does nothing if a compaction is already running or no segment is closed.
+    pub fn start_compaction(&mut self) -> Result<(), KvError> {
+        self.check_writable()?;
+        if self.compaction.is_some() || self.closed.is_empty() {
+            return Ok(());
+        }
+        self.ensure_loaded()?;
+
+        let mut sources = Vec::with_capacity(self.closed.len());
+        for (&id, f) in &self.closed {
+            sources.push((id, f.try_clone()?));
+        }
+        // Every segment older than the active one is compacted, so tombstones
+        // can be dropped: there is no older record left for them to hide
+        let now = now_millis();
+        let entries = self
+            .index
+            .iter()
+            .filter(|(_, location)| location.segment != self.segment && !location.is_expired(now))
+            .map(|(key, location)| (key.clone(), *location))
+            .collect();
+
+        self.compaction = Some(Compaction::start(
+            self.path.clone(),
+            sources,
+            entries,
+            self.version,
+            self.codec,
+            self.options.compression,
+        ));
+        Ok(())
+    }
+
+    /// Installs the result of a background compaction if it has finished,
+    /// or after waiting for it if `wait` is set
+    fn finish_compaction(&mut self, wait: bool) -> Result<(), KvError> {
+        match &self.compaction {
+            Some(compaction) if wait || compaction.is_finished() => {}
+            _ => return Ok(()),
+        }
+        let compacted = self.compaction.take().unwrap().join()?;
+        segment::install(&self.path, &compacted)?;
+
+        // Keys written since the compaction started already point elsewhere
+        for (key, old_location, new_location) in compacted.moved {
+            if self.index.get(&key) == Some(&old_location) {
+                self.index.insert(key, new_location);
+            }
+        }
+        for id in &compacted.sources {
+            self.closed.remove(id);
+            self.blooms.remove(id);
+        }
+        let target = *compacted.sources.last().unwrap();
+        self.closed.insert(target, compacted.file);
+        if self.options.bloom_filter.is_some() {
+            self.blooms.insert(target, self.build_bloom(target, 0));
+            self.save_bloom(target)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the index if `load` hasn't been called: compaction keeps
+    /// only the keys in the index, and would drop the others
+    fn ensure_loaded(&mut self) -> Result<(), KvError> {
+        if !self.loaded {
+            self.load()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the store, writing a hint file so the next `load`
+    /// doesn't need to scan the whole file.
+    /// If the store was never loaded, there is no index to write: any
+    /// existing hint is deleted instead, since the file has grown past it.
+    pub fn close(mut self) -> Result<(), KvError> {
+        if self.options.read_only {
+            return Ok(());
+        }
+        self.finish_compaction(true)?;
+        self.sync()?;
+        for &segment in self.blooms.keys() {
+            self.save_bloom(segment)?;
+        }
+        if !self.loaded {
+            return self.remove_hint();
+        }
+        self.write_hint()
+    }
+
+    fn write_hint(&mut self) -> Result<(), KvError> {
+        if self.segmented {
+            return Ok(());
+        }
+
+        // An uncommitted batch at the end must be read again by the next load,
+        // so that it is recognized and ignored
+        let data_len = match self.uncommitted_batch {
+            Some(position) => position,
+            None => self.f.metadata()?.len(),
+        };
+        hint::write_hint(
+            &hint::hint_path(&self.path),
+            self.version,
+            data_len,
+            ActionKv::fingerprint(&self.f, data_len)?,
+            self.index.iter(),
+        )?;
+        Ok(())
+    }
+
+    /// Checksum of the first and last `FINGERPRINT_LEN` bytes of a file
+    /// up to `data_len`, which tells a hint whether the file is still the
+    /// one it was written for without reading it all
+    fn fingerprint(f: &File, data_len: u64) -> io::Result<u32> {
+        let mut digest = CRC_32_ISO_HDLC_CALC.digest();
+        let head_len = data_len.min(FINGERPRINT_LEN);
+        let tail_start = data_len - head_len;
+        for start in [0, tail_start] {
+            let mut buf = vec![0; head_len as usize];
+            PositionalReader { f, position: start }.read_exact(&mut buf)?;
+            digest.update(&buf);
+        }
+        Ok(digest.finalize())
+    }
+
+    fn remove_hint(&self) -> Result<(), KvError> {
+        if self.segmented {
+            return Ok(());
+        }
+        match std::fs::remove_file(hint::hint_path(&self.path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
     }
 
     pub fn seek_to_end(&mut self) -> io::Result<u64> {
         self.f.seek(SeekFrom::End(0))
     }
 
-    /// Read the (key, value) pair at a given position in the file
-    fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
-        let mut reader = BufReader::new(&mut self.f);
-        reader.seek(SeekFrom::Start(position))?;
-        let kv = ActionKv::process_record(&mut reader)?;
-        Ok(kv)
+    /// Read the (key, value) pair at a given location
+    fn get_at(&self, location: &Location) -> Result<KeyValuePair, KvError> {
+        let record = self.files().read(location, self.version)?;
+        Ok(KeyValuePair {
+            key: record.key,
+            value: record.value,
+        })
+    }
+
+    pub(crate) fn read_record_at(f: &File, position: u64, version: u32) -> Result<Record, KvError> {
+        let mut reader = BufReader::new(PositionalReader { f, position });
+        ActionKv::process_record(&mut reader, version, position)
+    }
+}
+
+impl Drop for ActionKv {
+    /// Syncs writes the sync policy left pending, unless it is
+    /// `SyncPolicy::Never`. Errors can't be reported from here:
+    /// call `close` or `sync` to see them.
+    fn drop(&mut self) {
+        if self.unsynced_writes > 0 && self.options.sync != SyncPolicy::Never {
+            let _ = self.sync();
+        }
+    }
+}
+
+/// Reads a file from a given position with `pread`, leaving the
+/// file cursor alone, so it only needs a shared reference to the file
+struct PositionalReader<'a> {
+    f: &'a File,
+    position: u64,
+}
+
+impl Read for PositionalReader<'_> {
+    #[cfg(unix)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        let n = self.f.read_at(buf, self.position)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    // seek_read moves the file cursor on Windows, but appends and
+    // loads always seek before using it
+    #[cfg(windows)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::windows::fs::FileExt;
+
+        let n = self.f.seek_read(buf, self.position)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// Iterator over (key, value) pairs of an `ActionKv`, in key byte order.
+/// Values are read from the backing file as the iterator advances.
+/// Keys that had expired when the iterator was created are skipped.
+pub struct Iter<'a> {
+    pub(crate) entries: btree_map::Range<'a, ByteString, Location>,
+
+    /// Stop at the first key without this prefix
+    pub(crate) prefix: Option<ByteString>,
+
+    pub(crate) files: Files<'a>,
+    pub(crate) version: u32,
+
+    /// Keys that have expired by this time are skipped
+    pub(crate) now: u64,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<KeyValuePair, KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, location) = loop {
+            let (key, location) = self.entries.next()?;
+            if !location.is_expired(self.now) {
+                break (key, location);
+            }
+        };
+        if let Some(prefix) = &self.prefix {
+            if !key.starts_with(prefix) {
+                return None;
+            }
+        }
+
+        let record = self.files.read(location, self.version);
+        Some(record.map(|record| KeyValuePair {
+            key: record.key,
+            value: record.value,
+        }))
     }
 }
@@ -0,0 +1,46 @@
+use std::{fs::File, io};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockMode {
+    /// Many readers may hold the lock together
+    Shared,
+
+    /// A single writer holds the lock
+    Exclusive,
+}
+
+/// Takes an advisory lock (`flock`) on a file without blocking.
+/// Returns `Ok(false)` if a conflicting lock is already held,
+/// by another process or another handle to the same file.
+/// The lock is released when the file is closed.
+#[cfg(not(windows))]
+pub(crate) fn try_lock(f: &File, mode: LockMode) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let operation = match mode {
+        LockMode::Shared => libc::LOCK_SH,
+        LockMode::Exclusive => libc::LOCK_EX,
+    } | libc::LOCK_NB;
+
+    loop {
+        if unsafe { libc::flock(f.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EWOULDBLOCK) => return Ok(false),
+            Some(libc::EINTR) => continue,
+            _ => return Err(err),
+        }
+    }
+}
+
+/// Not implemented on Windows: the lock is always granted. The standard
+/// library opens files there with every sharing mode allowed, so nothing
+/// keeps a second writer from opening the same store and interleaving its
+/// records with the first one's.
+#[cfg(windows)]
+pub(crate) fn try_lock(_f: &File, _mode: LockMode) -> io::Result<bool> {
+    Ok(true)
+}
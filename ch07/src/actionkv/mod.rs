@@ -1,2 +1,25 @@
+pub mod batch;
+mod bloom;
+pub mod change;
+pub mod codec;
+pub mod error;
+pub mod export;
+mod hint;
 pub mod kv;
+mod lock;
+pub mod net;
+pub mod options;
+mod segment;
+pub mod shared;
+pub mod snapshot;
+pub mod typed;
+pub use batch::*;
+pub use change::*;
+pub use codec::*;
+pub use error::*;
+pub use export::ExportFormat;
 pub use kv::*;
+pub use options::*;
+pub use shared::*;
+pub use snapshot::*;
+pub use typed::*;
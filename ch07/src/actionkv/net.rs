@@ -0,0 +1,341 @@
+//! A small length-prefixed binary protocol to share a store over TCP.
+//!
+//! Every message is a frame: a u32 (little-endian) payload length, then the payload.
+//! A request payload is an operation code followed by its arguments;
+//! a response payload is a status code followed by its data.
+//! Byte strings inside a payload are written as a u32 length and the bytes.
+//! No frame may be larger than `MAX_FRAME_LEN`: a response that would be is
+//! answered with an error instead, such as a list of too many pairs.
+
+use std::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{KeyValuePair, KvError, SharedKv};
+
+/// Frames larger than this are rejected instead of allocated
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+/// How long the server waits after failing to accept a connection
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Get(Vec<u8>),
+    Insert(Vec<u8>, Vec<u8>),
+    Update(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+
+    /// List the pairs whose keys start with a prefix
+    List(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// The write was applied
+    Ok,
+    Value(Vec<u8>),
+    NotFound,
+    Pairs(Vec<(Vec<u8>, Vec<u8>)>),
+    Error(String),
+}
+
+impl Request {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut payload = Vec::new();
+        match self {
+            Request::Get(key) => {
+                payload.push(1);
+                write_bytes(&mut payload, key)?;
+            }
+            Request::Insert(key, value) => {
+                payload.push(2);
+                write_bytes(&mut payload, key)?;
+                write_bytes(&mut payload, value)?;
+            }
+            Request::Update(key, value) => {
+                payload.push(3);
+                write_bytes(&mut payload, key)?;
+                write_bytes(&mut payload, value)?;
+            }
+            Request::Delete(key) => {
+                payload.push(4);
+                write_bytes(&mut payload, key)?;
+            }
+            Request::List(prefix) => {
+                payload.push(5);
+                write_bytes(&mut payload, prefix)?;
+            }
+        }
+        write_frame(writer, &payload)
+    }
+
+    /// Reads the next request; `None` means the peer closed the connection
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Request>> {
+        let payload = match read_frame(reader)? {
+            None => return Ok(None),
+            Some(payload) => payload,
+        };
+
+        let mut payload = payload.as_slice();
+        let request = match payload.read_u8()? {
+            1 => Request::Get(read_bytes(&mut payload)?),
+            2 => Request::Insert(read_bytes(&mut payload)?, read_bytes(&mut payload)?),
+            3 => Request::Update(read_bytes(&mut payload)?, read_bytes(&mut payload)?),
+            4 => Request::Delete(read_bytes(&mut payload)?),
+            5 => Request::List(read_bytes(&mut payload)?),
+            op => return Err(invalid_data(format!("Unknown operation {}", op))),
+        };
+        Ok(Some(request))
+    }
+}
+
+impl Response {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut payload = Vec::new();
+        match self {
+            Response::Ok => payload.push(0),
+            Response::Value(value) => {
+                payload.push(1);
+                write_bytes(&mut payload, value)?;
+            }
+            Response::NotFound => payload.push(2),
+            Response::Pairs(pairs) => {
+                payload.push(3);
+                payload.write_u32::<LittleEndian>(pairs.len() as u32)?;
+                for (key, value) in pairs {
+                    write_bytes(&mut payload, key)?;
+                    write_bytes(&mut payload, value)?;
+                }
+            }
+            Response::Error(message) => {
+                payload.push(4);
+                write_bytes(&mut payload, message.as_bytes())?;
+            }
+        }
+        write_frame(writer, &payload)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Response> {
+        let payload = read_frame(reader)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+
+        let mut payload = payload.as_slice();
+        let response = match payload.read_u8()? {
+            0 => Response::Ok,
+            1 => Response::Value(read_bytes(&mut payload)?),
+            2 => Response::NotFound,
+            3 => {
+                let count = payload.read_u32::<LittleEndian>()?;
+                let mut pairs = Vec::new();
+                for _ in 0..count {
+                    pairs.push((read_bytes(&mut payload)?, read_bytes(&mut payload)?));
+                }
+                Response::Pairs(pairs)
+            }
+            4 => Response::Error(String::from_utf8_lossy(&read_bytes(&mut payload)?).into_owned()),
+            status => return Err(invalid_data(format!("Unknown status {}", status))),
+        };
+        Ok(response)
+    }
+}
+
+/// Fails with `InvalidInput`, writing nothing, if the payload is too large
+fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Message of {} bytes is larger than the limit of {} bytes",
+                payload.len(),
+                MAX_FRAME_LEN
+            ),
+        ));
+    }
+    writer.write_u32::<LittleEndian>(payload.len() as u32)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Reads a frame's payload, or `None` if the stream ends before a new frame
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data(format!("Frame of {} bytes is too large", len)));
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+fn write_bytes(payload: &mut Vec<u8>, bytes: &[u8]) -> io::Result<()> {
+    payload.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    payload.write_all(bytes)
+}
+
+fn read_bytes(payload: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let len = payload.read_u32::<LittleEndian>()? as usize;
+    if len > payload.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (bytes, rest) = payload.split_at(len);
+    *payload = rest;
+    Ok(bytes.to_vec())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Accepts connections forever, serving each one on its own thread.
+/// A connection that fails before it is accepted, or runs into a limit
+/// such as the number of open files, is logged and skipped.
+pub fn serve(listener: TcpListener, store: SharedKv) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Accepting a connection failed: {}", err);
+                // Running out of file descriptors fails every accept until one is closed
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            }
+        };
+        let store = store.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr();
+            if let Err(err) = handle_connection(stream, &store) {
+                eprintln!("Connection {:?} failed: {}", peer, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answers requests on one connection until the client disconnects
+pub fn handle_connection(stream: TcpStream, store: &SharedKv) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    while let Some(request) = Request::read_from(&mut reader)? {
+        let response =
+            execute(store, request).unwrap_or_else(|err| Response::Error(err.to_string()));
+        match response.write_to(&mut writer) {
+            Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                Response::Error(err.to_string()).write_to(&mut writer)?
+            }
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+fn execute(store: &SharedKv, request: Request) -> Result<Response, KvError> {
+    let response = match request {
+        Request::Get(key) => match store.get(&key)? {
+            Some(value) => Response::Value(value),
+            None => Response::NotFound,
+        },
+        Request::Insert(key, value) => {
+            store.insert(&key, &value)?;
+            Response::Ok
+        }
+        Request::Update(key, value) => {
+            store.update(&key, &value)?;
+            Response::Ok
+        }
+        Request::Delete(key) => {
+            store.delete(&key)?;
+            Response::Ok
+        }
+        Request::List(prefix) => {
+            let pairs = store
+                .read()
+                .scan_prefix(&prefix)
+                .map(|kv| kv.map(|kv| (kv.key, kv.value)))
+                .collect::<Result<_, _>>()?;
+            Response::Pairs(pairs)
+        }
+    };
+    Ok(response)
+}
+
+/// A connection to a `kv_server`
+#[derive(Debug)]
+pub struct KvClient {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl KvClient {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(KvClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    /// Sends a request and waits for its response
+    pub fn request(&mut self, request: &Request) -> io::Result<Response> {
+        request.write_to(&mut self.writer)?;
+        Response::read_from(&mut self.reader)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self.request(&Request::Get(key.to_vec()))? {
+            Response::Value(value) => Ok(Some(value)),
+            Response::NotFound => Ok(None),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.expect_ok(&Request::Insert(key.to_vec(), value.to_vec()))
+    }
+
+    pub fn update(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.expect_ok(&Request::Update(key.to_vec(), value.to_vec()))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<()> {
+        self.expect_ok(&Request::Delete(key.to_vec()))
+    }
+
+    pub fn list(&mut self, prefix: &[u8]) -> io::Result<Vec<KeyValuePair>> {
+        match self.request(&Request::List(prefix.to_vec()))? {
+            Response::Pairs(pairs) => Ok(pairs
+                .into_iter()
+                .map(|(key, value)| KeyValuePair { key, value })
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn expect_ok(&mut self, request: &Request) -> io::Result<()> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// Turns an error response, or a response of the wrong kind, into an error
+fn unexpected(response: Response) -> io::Error {
+    match response {
+        Response::Error(message) => io::Error::other(message),
+        response => invalid_data(format!("Unexpected response {:?}", response)),
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use super::Codec;
+
+/// When writes are forced to stable storage with `fsync`.
+/// Until then, acknowledged writes sit in the operating system's cache
+/// and can be lost on power failure, though not on a process crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Never sync implicitly; only `ActionKv::sync` and `close` do
+    #[default]
+    Never,
+
+    /// Sync after every write, before it is acknowledged
+    EveryWrite,
+
+    /// Sync once this many writes have accumulated since the last sync,
+    /// and when the store is closed or dropped
+    EveryN(usize),
+
+    /// Sync on the first write after this much time has passed since the last sync.
+    /// Nothing is synced while no writes come, until `ActionKv::sync_if_due`,
+    /// `close` or dropping the store.
+    Interval(Duration),
+}
+
+/// How values are compressed when records are written.
+/// Reading is unaffected: each record says whether its value is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+
+    /// LZ4 block compression. Values that don't shrink are stored as they are.
+    Lz4,
+}
+
+/// Settings used when opening a store with `ActionKv::open_with`
+#[derive(Debug, Clone, Default)]
+pub struct KvOptions {
+    pub sync: SyncPolicy,
+
+    /// Open the store for reading only. Any number of read-only handles can
+    /// share a store, but not with a writer. Writes fail with `KvError::ReadOnly`.
+    pub read_only: bool,
+
+    /// Codec recorded in the header of a newly created store.
+    /// `None` means keys and values are raw bytes.
+    /// It is ignored when opening an existing store: see `ActionKv::codec`.
+    pub codec: Option<Codec>,
+
+    /// Compression of the values written through this handle
+    pub compression: Compression,
+
+    /// Keep the store as a directory of numbered segment files, starting a new
+    /// segment once the active one has grown to this many bytes.
+    /// A segment can exceed it by the last record or batch written to it.
+    /// The size is saved in the directory: opening it again without a size
+    /// uses the saved one, and with a different size replaces it.
+    /// Opening a path that is already a directory also selects this layout;
+    /// if no size was ever saved, its active segment grows without limit.
+    pub segment_size: Option<u64>,
+
+    /// Start compacting the closed segments in the background once there are
+    /// this many of them. 0 leaves compaction to `ActionKv::compact`.
+    pub compact_segments: usize,
+
+    /// Keep a Bloom filter of the keys of each segment, aiming for this
+    /// false-positive rate, e.g. 0.01. See `ActionKv::may_contain`.
+    pub bloom_filter: Option<f64>,
+}
@@ -0,0 +1,306 @@
+//! Stores kept as a directory of segment files.
+//!
+//! Each segment is a complete log file, header included, named after its id:
+//! `00000000.seg`, `00000001.seg`, ... Records are only appended to the segment
+//! with the highest id, the active one; the others are closed and never written
+//! again, so they can be compacted on another thread while writes go on.
+//!
+//! Compacting closed segments copies their live records into a `.compact` file
+//! named after the newest of them. Once it is complete, it is renamed to
+//! `.compacted`, which marks the compaction as done; the closed segments are
+//! then deleted and the `.compacted` file takes their place as a `.seg` file.
+//! Opening a store finishes an install interrupted by a crash, and discards
+//! an incomplete `.compact` file. The directory is synced after each step,
+//! so that a crash can't leave the steps applied out of order.
+//!
+//! The segment size is saved in a `segment-size` file, so that the store
+//! can be opened again without repeating it.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+use super::bloom;
+use super::kv::{ActionKv, Location, Record, RecordKind, HEADER_LEN};
+use super::{Codec, Compression, KvError};
+
+type ByteString = Vec<u8>;
+
+/// Each live record copied by a compaction: its key, where it was and where it is now
+type Moved = Vec<(ByteString, Location, Location)>;
+
+const SEGMENT_EXTENSION: &str = "seg";
+const COMPACT_EXTENSION: &str = "compact";
+const COMPACTED_EXTENSION: &str = "compacted";
+const SEGMENT_SIZE_FILE: &str = "segment-size";
+
+pub(crate) fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:08}.{}", id, SEGMENT_EXTENSION))
+}
+
+fn compact_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:08}.{}", id, COMPACT_EXTENSION))
+}
+
+fn compacted_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:08}.{}", id, COMPACTED_EXTENSION))
+}
+
+/// Makes the renames and deletions in a directory durable.
+/// Windows can't open a directory as a file; its renames are durable once
+/// they return on NTFS.
+#[cfg(not(windows))]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(windows)]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The segment size saved in a directory, if any
+pub(crate) fn read_segment_size(dir: &Path) -> io::Result<Option<u64>> {
+    let text = match fs::read_to_string(dir.join(SEGMENT_SIZE_FILE)) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    match text.trim().parse() {
+        Ok(size) => Ok(Some(size)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid segment size {:?}", text.trim()),
+        )),
+    }
+}
+
+/// Saves the segment size in a directory, replacing the one saved before
+pub(crate) fn save_segment_size(dir: &Path, size: u64) -> io::Result<()> {
+    let path = dir.join(SEGMENT_SIZE_FILE);
+    let tmp_path = path.with_extension("tmp");
+    let mut f = File::create(&tmp_path)?;
+    writeln!(f, "{}", size)?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
+    sync_dir(dir)
+}
+
+/// Ids of the files with a given extension in a directory, in ascending order
+fn list_ids(dir: &Path, extension: &str) -> io::Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(extension) {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Ids of the segments in a directory, in ascending order
+pub(crate) fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    list_ids(dir, SEGMENT_EXTENSION)
+}
+
+/// Whether a crash interrupted the install of a compaction, which only
+/// a writer can finish
+pub(crate) fn has_interrupted_install(dir: &Path) -> io::Result<bool> {
+    Ok(!list_ids(dir, COMPACTED_EXTENSION)?.is_empty())
+}
+
+/// Cleans up after compactions interrupted by a crash.
+/// Incomplete output is deleted; complete output is installed.
+pub(crate) fn finish_interrupted_compactions(dir: &Path) -> io::Result<()> {
+    for id in list_ids(dir, COMPACT_EXTENSION)? {
+        fs::remove_file(compact_path(dir, id))?;
+    }
+    sync_dir(dir)?;
+    for id in list_ids(dir, COMPACTED_EXTENSION)? {
+        // A compaction takes in every segment up to its own id
+        let sources: Vec<u64> = list_segments(dir)?
+            .into_iter()
+            .filter(|&source| source <= id)
+            .collect();
+        replace_sources(dir, id, &sources)?;
+    }
+    Ok(())
+}
+
+/// The files of a store, to find the one a location points into
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Files<'a> {
+    /// Id of the active segment: 0 for a single-file store
+    pub segment: u64,
+    pub active: &'a File,
+    pub closed: &'a BTreeMap<u64, File>,
+}
+
+impl<'a> Files<'a> {
+    pub(crate) fn get(&self, segment: u64) -> Result<&'a File, KvError> {
+        if segment == self.segment {
+            return Ok(self.active);
+        }
+        self.closed.get(&segment).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Segment {} is not part of the store", segment),
+            )
+            .into()
+        })
+    }
+
+    pub(crate) fn read(&self, location: &Location, version: u32) -> Result<Record, KvError> {
+        ActionKv::read_record_at(self.get(location.segment)?, location.offset, version)
+    }
+}
+
+/// What a compaction of closed segments produced
+#[derive(Debug)]
+pub(crate) struct Compacted {
+    /// The segments that were compacted, oldest first
+    pub sources: Vec<u64>,
+
+    /// The compacted segment, not yet renamed into place.
+    /// It takes over the id of the newest source.
+    pub file: File,
+
+    pub moved: Moved,
+}
+
+/// A compaction of closed segments running on a background thread
+#[derive(Debug)]
+pub(crate) struct Compaction {
+    handle: JoinHandle<Result<Compacted, KvError>>,
+}
+
+impl Compaction {
+    /// Starts copying the records at `entries`, which must all live in
+    /// `sources`, to a new segment.
+    pub(crate) fn start(
+        dir: PathBuf,
+        sources: Vec<(u64, File)>,
+        mut entries: Vec<(ByteString, Location)>,
+        version: u32,
+        codec: Option<Codec>,
+        compression: Compression,
+    ) -> Compaction {
+        let handle = thread::spawn(move || {
+            let target = sources.last().expect("nothing to compact").0;
+            let tmp_path = compact_path(&dir, target);
+            let result = compact_into(
+                &tmp_path,
+                target,
+                &sources,
+                &mut entries,
+                version,
+                codec,
+                compression,
+            );
+            if result.is_err() {
+                let _ = fs::remove_file(&tmp_path);
+            }
+            result.map(|(file, moved)| Compacted {
+                sources: sources.iter().map(|(id, _)| *id).collect(),
+                file,
+                moved,
+            })
+        });
+        Compaction { handle }
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the compaction to end
+    pub(crate) fn join(self) -> Result<Compacted, KvError> {
+        self.handle.join().expect("segment compaction panicked")
+    }
+}
+
+fn compact_into(
+    tmp_path: &Path,
+    target: u64,
+    sources: &[(u64, File)],
+    entries: &mut [(ByteString, Location)],
+    version: u32,
+    codec: Option<Codec>,
+    compression: Compression,
+) -> Result<(File, Moved), KvError> {
+    let tmp_file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create_new(true)
+        .open(tmp_path)?;
+
+    // Copy records in file order to keep reads of the old segments sequential
+    entries.sort_by_key(|(_, location)| (location.segment, location.offset));
+    let files: BTreeMap<u64, &File> = sources.iter().map(|(id, f)| (*id, f)).collect();
+
+    let mut moved = Vec::with_capacity(entries.len());
+    let mut writer = BufWriter::new(&tmp_file);
+    ActionKv::write_header(&mut writer, codec)?;
+    let mut position = HEADER_LEN;
+    for (key, old_location) in entries.iter() {
+        let f = files[&old_location.segment];
+        let record = ActionKv::read_record_at(f, old_location.offset, version)?;
+        let len = ActionKv::write_record(
+            &mut writer,
+            RecordKind::Put,
+            &record.key,
+            &record.value,
+            record.expires_at,
+            compression,
+        )?;
+        let new_location = Location {
+            segment: target,
+            offset: position,
+            len,
+            expires_at: record.expires_at,
+        };
+        moved.push((key.clone(), *old_location, new_location));
+        position += len;
+    }
+    writer.flush()?;
+    drop(writer);
+    tmp_file.sync_all()?;
+
+    Ok((tmp_file, moved))
+}
+
+/// Renames a finished compaction into place and deletes the segments it replaces.
+/// No source may be deleted before the compaction is marked as done:
+/// a crash would leave a log missing either live values or the tombstones
+/// hiding older ones.
+pub(crate) fn install(dir: &Path, compacted: &Compacted) -> io::Result<()> {
+    let target = *compacted.sources.last().expect("nothing to compact");
+    fs::rename(compact_path(dir, target), compacted_path(dir, target))?;
+    sync_dir(dir)?;
+    replace_sources(dir, target, &compacted.sources)
+}
+
+fn replace_sources(dir: &Path, target: u64, sources: &[u64]) -> io::Result<()> {
+    for &id in sources {
+        bloom::remove_filter(&segment_path(dir, id))?;
+        match fs::remove_file(segment_path(dir, id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    sync_dir(dir)?;
+    fs::rename(compacted_path(dir, target), segment_path(dir, target))?;
+    sync_dir(dir)
+}
@@ -0,0 +1,63 @@
+use std::{
+    sync::{mpsc::Receiver, Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
+};
+
+use super::{ActionKv, Change, KvError, WriteBatch};
+
+/// A handle to an `ActionKv` that can be cloned and sent to other threads.
+/// Any number of threads can read at the same time, while writes are
+/// serialized and wait for readers to finish.
+#[derive(Debug, Clone)]
+pub struct SharedKv {
+    inner: Arc<RwLock<ActionKv>>,
+}
+
+impl SharedKv {
+    /// Wraps a store, which should already be loaded
+    pub fn new(store: ActionKv) -> Self {
+        SharedKv {
+            inner: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        self.read().get(key)
+    }
+
+    pub fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), KvError> {
+        self.write().insert(key, value)
+    }
+
+    pub fn insert_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<(), KvError> {
+        self.write().insert_with_ttl(key, value, ttl)
+    }
+
+    pub fn update(&self, key: &[u8], value: &[u8]) -> Result<(), KvError> {
+        self.write().update(key, value)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<(), KvError> {
+        self.write().delete(key)
+    }
+
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<(), KvError> {
+        self.write().write_batch(batch)
+    }
+
+    /// See `ActionKv::subscribe`
+    pub fn subscribe(&self) -> Receiver<Change> {
+        self.write().subscribe()
+    }
+
+    /// Shared access to the store, e.g. to iterate over it.
+    /// Writers are blocked until the guard is dropped.
+    pub fn read(&self) -> RwLockReadGuard<'_, ActionKv> {
+        self.inner.read().expect("ActionKv lock poisoned")
+    }
+
+    /// Exclusive access to the store
+    pub fn write(&self) -> RwLockWriteGuard<'_, ActionKv> {
+        self.inner.write().expect("ActionKv lock poisoned")
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    ops::{Bound, RangeBounds},
+};
+
+use super::kv::{now_millis, Iter, Location, Position};
+use super::segment::Files;
+use super::KvError;
+
+type ByteString = Vec<u8>;
+type ByteStr = [u8];
+
+/// A read-only view of a store as it was at a given end offset of its log.
+///
+/// The view keeps its own handle to the backing file and its own copy of
+/// the index, so later writes to the store don't change what it sees.
+/// On Unix it even survives `compact`: the compacted file replaces the old one
+/// under the same path, but the view still reads the old file.
+/// The same goes for the segments of a segmented store.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub(crate) f: File,
+    pub(crate) segment: u64,
+    pub(crate) closed: BTreeMap<u64, File>,
+    pub(crate) version: u32,
+    pub(crate) end: Position,
+    pub(crate) index: BTreeMap<ByteString, Location>,
+}
+
+impl Snapshot {
+    /// End of the log when the snapshot was taken.
+    /// Records starting at or after this position are not visible.
+    pub fn end(&self) -> Position {
+        self.end
+    }
+
+    fn files(&self) -> Files<'_> {
+        Files {
+            segment: self.segment,
+            active: &self.f,
+            closed: &self.closed,
+        }
+    }
+
+    /// Number of live keys in the snapshot, leaving out those that have expired since
+    pub fn len(&self) -> usize {
+        let now = now_millis();
+        self.index
+            .values()
+            .filter(|location| !location.is_expired(now))
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Look up the value a key had when the snapshot was taken
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>, KvError> {
+        match self.index.get(key) {
+            Some(location) if location.is_expired(now_millis()) => Ok(None),
+            None => Ok(None),
+            Some(location) => {
+                let record = self.files().read(location, self.version)?;
+                Ok(Some(record.value))
+            }
+        }
+    }
+
+    /// Iterate over all (key, value) pairs of the snapshot in key byte order
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(..)
+    }
+
+    /// Iterate over the (key, value) pairs whose keys fall in `range`,
+    /// in key byte order
+    pub fn range<R>(&self, range: R) -> Iter<'_>
+    where
+        R: RangeBounds<ByteStr>,
+    {
+        Iter {
+            entries: self.index.range::<ByteStr, _>(range),
+            prefix: None,
+            files: self.files(),
+            version: self.version,
+            now: now_millis(),
+        }
+    }
+
+    /// Iterate over the (key, value) pairs whose keys start with `prefix`,
+    /// in key byte order
+    pub fn scan_prefix(&self, prefix: &ByteStr) -> Iter<'_> {
+        Iter {
+            entries: self
+                .index
+                .range::<ByteStr, _>((Bound::Included(prefix), Bound::Unbounded)),
+            prefix: Some(prefix.to_vec()),
+            files: self.files(),
+            version: self.version,
+            now: now_millis(),
+        }
+    }
+}
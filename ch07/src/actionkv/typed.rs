@@ -0,0 +1,94 @@
+use std::{marker::PhantomData, path::Path, time::Duration};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{ActionKv, Codec, KvError, KvOptions};
+
+/// A store of typed keys and values, serialized with a `Codec`.
+/// The codec is written in the file header when the store is created,
+/// and opening the store with another codec fails instead of
+/// returning garbage.
+#[derive(Debug)]
+pub struct TypedKv<K, V> {
+    store: ActionKv,
+    codec: Codec,
+    _types: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> TypedKv<K, V>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+{
+    /// Open a typed store, creating it with `codec` if it doesn't exist
+    pub fn open(path: &Path, codec: Codec) -> Result<Self, KvError> {
+        TypedKv::open_with(path, codec, KvOptions::default())
+    }
+
+    pub fn open_with(path: &Path, codec: Codec, options: KvOptions) -> Result<Self, KvError> {
+        let options = KvOptions {
+            codec: Some(codec),
+            ..options
+        };
+        let store = ActionKv::open_with(path, options)?;
+        if store.codec() != Some(codec) {
+            return Err(KvError::CodecMismatch {
+                expected: Some(codec),
+                found: store.codec(),
+            });
+        }
+
+        Ok(TypedKv {
+            store,
+            codec,
+            _types: PhantomData,
+        })
+    }
+
+    pub fn load(&mut self) -> Result<(), KvError> {
+        self.store.load()
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, KvError> {
+        let key = self.codec.encode(key)?;
+        match self.store.get(&key)? {
+            None => Ok(None),
+            Some(value) => self.codec.decode(&value).map(Some),
+        }
+    }
+
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<(), KvError> {
+        let key = self.codec.encode(key)?;
+        let value = self.codec.encode(value)?;
+        self.store.insert(&key, &value)
+    }
+
+    /// Insert a pair that expires after `ttl`, see `ActionKv::insert_with_ttl`
+    pub fn insert_with_ttl(&mut self, key: &K, value: &V, ttl: Duration) -> Result<(), KvError> {
+        let key = self.codec.encode(key)?;
+        let value = self.codec.encode(value)?;
+        self.store.insert_with_ttl(&key, &value, ttl)
+    }
+
+    pub fn update(&mut self, key: &K, value: &V) -> Result<(), KvError> {
+        self.insert(key, value)
+    }
+
+    pub fn delete(&mut self, key: &K) -> Result<(), KvError> {
+        let key = self.codec.encode(key)?;
+        self.store.delete(&key)
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// The underlying store of encoded keys and values
+    pub fn into_inner(self) -> ActionKv {
+        self.store
+    }
+
+    pub fn close(self) -> Result<(), KvError> {
+        self.store.close()
+    }
+}
@@ -0,0 +1,61 @@
+use ch07::actionkv::net::KvClient;
+
+const USAGE: &str = "
+Usage:
+    kv_client ADDR get KEY
+    kv_client ADDR delete KEY
+    kv_client ADDR insert KEY VALUE
+    kv_client ADDR update KEY VALUE
+    kv_client ADDR list [PREFIX]
+";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let addr = args.get(1).expect(USAGE);
+    let action = args.get(2).expect(USAGE).as_ref();
+    let maybe_key: Option<&String> = args.get(3);
+    let maybe_value: Option<&String> = args.get(4);
+
+    let mut client = KvClient::connect(addr.as_str()).expect("Unable to connect");
+
+    match action {
+        "get" => {
+            let key = maybe_key.expect(USAGE).as_bytes();
+            match client.get(key).unwrap() {
+                None => {
+                    eprintln!("{:?} not found", key);
+                    std::process::exit(1);
+                }
+                Some(value) => println!("{}", String::from_utf8_lossy(&value)),
+            }
+        }
+        "insert" => {
+            let key = maybe_key.expect(USAGE).as_bytes();
+            let value = maybe_value.expect(USAGE).as_bytes();
+            client.insert(key, value).unwrap();
+        }
+        "update" => {
+            let key = maybe_key.expect(USAGE).as_bytes();
+            let value = maybe_value.expect(USAGE).as_bytes();
+            client.update(key, value).unwrap();
+        }
+        "delete" => {
+            let key = maybe_key.expect(USAGE).as_bytes();
+            client.delete(key).unwrap();
+        }
+        "list" => {
+            let prefix = maybe_key.map(|p| p.as_bytes()).unwrap_or(b"");
+            for kv in client.list(prefix).unwrap() {
+                println!(
+                    "{} -> {}",
+                    String::from_utf8_lossy(&kv.key),
+                    String::from_utf8_lossy(&kv.value)
+                );
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
@@ -1,43 +1,671 @@
-use ch07::actionkv::ActionKv;
-
-const USAGE: &str = "
-Usage:
-    kv_mem FILE get KEY
-    kv_mem FILE delete KEY
-    kv_mem FILE insert KEY VALUE
-    kv_mem FILE update KEY VALUE
-";
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let fname = args.get(1).expect(USAGE);
-    let action = args.get(2).expect(USAGE).as_ref();
-    let key = args.get(3).expect(USAGE).as_ref();
-    let maybe_value: Option<&String> = args.get(4);
-
-    let path = std::path::Path::new(&fname);
-    let mut store = ActionKv::open(path).expect("Unable to open file");
-    store.load().expect("UNable to load data");
+use std::{
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-    match action {
-        "insert" => {
-            let value = maybe_value.expect(USAGE).as_ref();
-            let _ = store.insert(key, value);
+use ch07::actionkv::{
+    export, ActionKv, Change, ExportFormat, KvError, KvOptions, Op, Position, Tail,
+};
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+
+/// A key wasn't found, or `verify` found damaged records
+const EXIT_UNSUCCESSFUL: u8 = 1;
+
+/// The command line, or a line typed in the shell, couldn't be understood
+const EXIT_USAGE: u8 = 2;
+
+/// The store couldn't be opened, read or written
+const EXIT_STORE_ERROR: u8 = 3;
+
+/// How often `tail` looks for new records once it has caught up
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+const EXIT_CODES: &str = "\
+Exit codes:
+    0  success
+    1  key not found, or damaged records found by verify
+    2  invalid arguments
+    3  the store could not be opened, read or written";
+
+/// Why a command didn't succeed
+enum Failure {
+    Unsuccessful,
+    Usage(String),
+    Store(KvError),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Unsuccessful => EXIT_UNSUCCESSFUL,
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Store(_) => EXIT_STORE_ERROR,
+        }
+    }
+
+    fn report(&self) {
+        match self {
+            Failure::Unsuccessful => {}
+            Failure::Usage(message) => eprintln!("error: {}", message),
+            Failure::Store(err) => eprintln!("error: {}", err),
+        }
+    }
+}
+
+impl From<KvError> for Failure {
+    fn from(err: KvError) -> Self {
+        Failure::Store(err)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::Store(err.into())
+    }
+}
+
+/// Settings given before the subcommand, which also apply inside the shell
+struct Settings {
+    /// Don't print the index or progress messages
+    quiet: bool,
+
+    /// Keys and values are hex-encoded, on input and output
+    hex: bool,
+}
+
+impl Settings {
+    /// Decodes a key or value given on the command line
+    fn input(&self, text: &str) -> Result<Vec<u8>, Failure> {
+        if self.hex {
+            hex::decode(text).map_err(|e| Failure::Usage(format!("invalid hex {:?}: {}", text, e)))
+        } else {
+            Ok(text.as_bytes().to_vec())
         }
-        "update" => {
-            let value = maybe_value.expect(USAGE).as_ref();
-            store.update(key, value).unwrap();
+    }
+
+    fn output(&self, bytes: &[u8]) -> String {
+        if self.hex {
+            hex::encode(bytes)
+        } else {
+            String::from_utf8_lossy(bytes).into_owned()
         }
+    }
 
-        "get" => match store.get(key).unwrap() {
-            None => eprintln!("{:?} not found", key),
-            Some(value) => println!(
-                "GET: {} -> {}",
-                String::from_utf8_lossy(key),
-                String::from_utf8_lossy(&value)
+    fn key(&self, matches: &ArgMatches) -> Result<Vec<u8>, Failure> {
+        self.input(matches.get_one::<String>("key").unwrap())
+    }
+
+    /// The value of `insert` or `update`, either given inline or read from `--file`
+    fn value(&self, matches: &ArgMatches) -> Result<Vec<u8>, Failure> {
+        match matches.get_one::<PathBuf>("file") {
+            Some(path) => Ok(std::fs::read(path)?),
+            None => self.input(matches.get_one::<String>("value").unwrap()),
+        }
+    }
+}
+
+fn key_arg() -> Arg {
+    Arg::new("key").required(true).help("The key")
+}
+
+fn value_args() -> [Arg; 3] {
+    [
+        Arg::new("value")
+            .required_unless_present("file")
+            .help("The value"),
+        Arg::new("file")
+            .long("file")
+            .value_name("PATH")
+            .value_parser(value_parser!(PathBuf))
+            .conflicts_with("value")
+            .help("Read the value from a file instead, byte for byte"),
+        Arg::new("ttl")
+            .long("ttl")
+            .value_name("DURATION")
+            .value_parser(parse_duration)
+            .help("Expire the key after this long, e.g. 90s, 10m, 2h or 1d"),
+    ]
+}
+
+fn format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .required(true)
+        .value_parser(["jsonl", "csv"])
+}
+
+/// Commands that work on a loaded store, both on the command line and in the shell
+fn store_commands() -> Vec<Command> {
+    vec![
+        Command::new("get")
+            .about("Print the value of a key")
+            .arg(key_arg())
+            .arg(
+                Arg::new("at-offset")
+                    .long("at-offset")
+                    .value_name("N")
+                    .value_parser(value_parser!(u64))
+                    .help("Read the store as it was when its log was N bytes long"),
+            )
+            .arg(
+                Arg::new("segment")
+                    .long("segment")
+                    .value_name("ID")
+                    .value_parser(value_parser!(u64))
+                    .requires("at-offset")
+                    .help("The segment --at-offset is in [default: the active segment]"),
+            ),
+        Command::new("insert")
+            .about("Set a key to a value")
+            .arg(key_arg())
+            .args(value_args()),
+        Command::new("update")
+            .about("Set a key to a value")
+            .arg(key_arg())
+            .args(value_args()),
+        Command::new("delete").about("Delete a key").arg(key_arg()),
+        Command::new("list")
+            .about("List the keys and values, optionally only those starting with PREFIX")
+            .arg(Arg::new("prefix").help("Only list keys starting with this")),
+        Command::new("history")
+            .about("List every version of a key still in the log")
+            .arg(key_arg()),
+        Command::new("stat").about("Show how much space the store uses"),
+        Command::new("compact").about("Rewrite the file with only the live records"),
+        Command::new("export")
+            .about("Write every live key and value to stdout")
+            .arg(format_arg()),
+        Command::new("import")
+            .about("Load the keys and values of an exported file in a single batch")
+            .arg(format_arg())
+            .arg(
+                Arg::new("path")
+                    .required(true)
+                    .value_parser(value_parser!(PathBuf)),
             ),
-        },
-        "delete" => store.delete(key).unwrap(),
-        _ => {}
+    ]
+}
+
+fn cli() -> Command {
+    command!()
+        .about("Reads and writes an ActionKv store")
+        .after_help(EXIT_CODES)
+        .subcommand_required(true)
+        .arg(
+            Arg::new("file")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .help("The store file, or directory of segments"),
+        )
+        .arg(
+            Arg::new("segment-size")
+                .long("segment-size")
+                .value_name("BYTES")
+                .value_parser(value_parser!(u64))
+                .help("Keep the store as a directory of segments of about this size; the store remembers it"),
+        )
+        .arg(
+            Arg::new("bloom-filter")
+                .long("bloom-filter")
+                .value_name("RATE")
+                .value_parser(value_parser!(f64))
+                .help("Keep a Bloom filter per segment with this false-positive rate, e.g. 0.01"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Don't print the index after loading, nor progress messages"),
+        )
+        .arg(
+            Arg::new("hex")
+                .long("hex")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Keys and values are given and printed hex-encoded"),
+        )
+        .subcommands(store_commands())
+        .subcommand(Command::new("verify").about("Check the checksum of every record"))
+        .subcommand(
+            Command::new("recover").about("Cut off a damaged record at the end of the file"),
+        )
+        .subcommand(Command::new("shell").about("Run commands interactively"))
+        .subcommand(
+            Command::new("tail")
+                .about("Print changes as they are appended to the log, like tail -f")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("OFFSET")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0")
+                        .help("Start with the first record at or after this offset"),
+                )
+                .arg(
+                    Arg::new("segment")
+                        .long("segment")
+                        .value_name("ID")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0")
+                        .help("The segment --from is in"),
+                )
+                .arg(
+                    Arg::new("no-follow")
+                        .long("no-follow")
+                        .action(ArgAction::SetTrue)
+                        .help("Stop at the end of the log instead of waiting for more"),
+                ),
+        )
+}
+
+/// The parser for lines typed in the shell
+fn shell_cli() -> Command {
+    Command::new("kv_mem")
+        .no_binary_name(true)
+        .subcommand_required(true)
+        .disable_version_flag(true)
+        .subcommands(store_commands())
+        .subcommand(Command::new("exit").alias("quit").about("Leave the shell"))
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+    let settings = Settings {
+        quiet: matches.get_flag("quiet"),
+        hex: matches.get_flag("hex"),
+    };
+
+    match run(&matches, &settings) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            failure.report();
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+fn run(matches: &ArgMatches, settings: &Settings) -> Result<(), Failure> {
+    let path = matches.get_one::<PathBuf>("file").unwrap();
+    let (action, sub_matches) = matches.subcommand().unwrap();
+
+    // Following the log takes no lock, so it doesn't open the store
+    if action == "tail" {
+        return tail(path, sub_matches, settings);
+    }
+
+    // Commands that only read can share the store with other readers
+    let options = KvOptions {
+        read_only: matches!(
+            action,
+            "get" | "list" | "history" | "stat" | "verify" | "export"
+        ),
+        segment_size: matches.get_one::<u64>("segment-size").copied(),
+        bloom_filter: matches.get_one::<f64>("bloom-filter").copied(),
+        ..Default::default()
+    };
+    let mut store = ActionKv::open_with(path, options)?;
+
+    // These inspect or repair the file, so they must not require a clean load
+    match action {
+        "verify" => {
+            let report = store.verify()?;
+            for (segment, err) in &report.errors {
+                if store.is_segmented() {
+                    print!("segment {}: ", segment);
+                }
+                println!("{}", err);
+            }
+            println!(
+                "{} records checked, {} bad",
+                report.records,
+                report.errors.len()
+            );
+            if !report.errors.is_empty() {
+                return Err(Failure::Unsuccessful);
+            }
+            return Ok(());
+        }
+        "recover" => {
+            let truncated = store.recover()?;
+            if !settings.quiet {
+                match truncated {
+                    None => println!("No damaged tail record found"),
+                    Some(offset) => println!("Truncated damaged tail at offset {}", offset),
+                }
+            }
+            return store.close().map_err(Failure::from);
+        }
+        _ => store.load()?,
+    }
+
+    // `load` prints nothing, so the index is shown here. The export goes to
+    // stdout, so it must be the only thing printed there.
+    if !settings.quiet && !matches!(action, "export" | "shell") {
+        println!("Index map:");
+        for (k, i) in store.index.iter() {
+            let position = Position {
+                segment: i.segment,
+                offset: i.offset,
+            };
+            println!(
+                "\t{} -> {}",
+                settings.output(k),
+                show_position(store.is_segmented(), position)
+            );
+        }
+    }
+
+    let result = match action {
+        "shell" => shell(&mut store, settings),
+        _ => execute(&mut store, action, sub_matches, settings),
+    };
+
+    // Keep what was written even if the command itself failed
+    store.close()?;
+    result
+}
+
+/// Runs one of the `store_commands` on a loaded store
+fn execute(
+    store: &mut ActionKv,
+    action: &str,
+    matches: &ArgMatches,
+    settings: &Settings,
+) -> Result<(), Failure> {
+    match action {
+        "insert" | "update" => {
+            let key = settings.key(matches)?;
+            let value = settings.value(matches)?;
+            match matches.get_one::<Duration>("ttl") {
+                Some(&ttl) => store.insert_with_ttl(&key, &value, ttl)?,
+                None => store.insert(&key, &value)?,
+            }
+        }
+        "delete" => store.delete(&settings.key(matches)?)?,
+        "get" => {
+            let key = settings.key(matches)?;
+            let value = match matches.get_one::<u64>("at-offset") {
+                Some(&offset) => {
+                    let segment = matches.get_one::<u64>("segment").copied();
+                    let end = Position {
+                        segment: segment.unwrap_or(store.active_segment()),
+                        offset,
+                    };
+                    store.snapshot_at(end)?.get(&key)?
+                }
+                // Consulting the filters counts towards the observed false-positive rate
+                None if !store.may_contain(&key) => None,
+                None => store.get(&key)?,
+            };
+            match value {
+                None => {
+                    eprintln!("{} not found", settings.output(&key));
+                    return Err(Failure::Unsuccessful);
+                }
+                Some(value) => println!(
+                    "GET: {} -> {}",
+                    settings.output(&key),
+                    settings.output(&value)
+                ),
+            }
+        }
+        "list" => {
+            let prefix = match matches.get_one::<String>("prefix") {
+                Some(prefix) => settings.input(prefix)?,
+                None => Vec::new(),
+            };
+            for kv in store.scan_prefix(&prefix) {
+                let kv = kv?;
+                println!(
+                    "{} -> {}",
+                    settings.output(&kv.key),
+                    settings.output(&kv.value)
+                );
+            }
+        }
+        "history" => {
+            let key = settings.key(matches)?;
+            for entry in store.history(&key)? {
+                let position = show_position(store.is_segmented(), entry.position);
+                match entry.value {
+                    Some(value) => println!(
+                        "{}: {}{}",
+                        position,
+                        settings.output(&value),
+                        show_expiry(entry.expires_at)
+                    ),
+                    None => println!("{}: <deleted>", position),
+                }
+            }
+        }
+        "stat" => {
+            let stats = store.stats()?;
+            println!("keys:          {}", stats.keys);
+            if store.is_segmented() {
+                println!("segments:      {}", store.segments().len());
+            }
+            println!("file size:     {} bytes", stats.file_bytes);
+            println!("live on disk:  {} bytes", stats.live_bytes);
+            println!("live logical:  {} bytes", stats.logical_bytes);
+            if stats.live_bytes > 0 {
+                println!(
+                    "ratio:         {:.2}",
+                    stats.logical_bytes as f64 / stats.live_bytes as f64
+                );
+            }
+            if let Some(bloom) = stats.bloom {
+                println!("bloom filters: {} ({} bits)", bloom.filters, bloom.bits);
+                println!(
+                    "bloom fp rate: {:.4}% estimated",
+                    bloom.estimated_false_positive_rate * 100.0
+                );
+                match bloom.observed_false_positive_rate() {
+                    Some(rate) => println!(
+                        "               {:.4}% observed ({} of {} lookups of absent keys)",
+                        rate * 100.0,
+                        bloom.false_positives,
+                        bloom.negatives + bloom.false_positives
+                    ),
+                    None => println!("               no lookups of absent keys yet"),
+                }
+            }
+        }
+        "compact" => store.compact()?,
+        "export" => {
+            let stdout = io::stdout().lock();
+            export::export(store, format(matches), BufWriter::new(stdout))?;
+        }
+        "import" => {
+            let f = File::open(matches.get_one::<PathBuf>("path").unwrap())?;
+            let count = export::import(store, format(matches), f)?;
+            if !settings.quiet {
+                println!("Imported {} keys", count);
+            }
+        }
+        _ => unreachable!("clap only accepts known subcommands"),
+    }
+    Ok(())
+}
+
+/// Parses a duration such as `500ms`, `90s`, `10m`, `2h` or `1d`.
+/// A bare number is a number of seconds.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", text))?;
+    let seconds = match unit {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {:?} in duration {:?}", unit, text)),
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration {:?} is too long", text))
+}
+
+fn show_expiry(expires_at: Option<u64>) -> String {
+    let expires_at = match expires_at {
+        None => return String::new(),
+        Some(expires_at) => Duration::from_millis(expires_at),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    match expires_at.checked_sub(now) {
+        Some(left) if !left.is_zero() => format!(" (expires in {}s)", left.as_secs()),
+        _ => " (expired)".to_string(),
+    }
+}
+
+/// Offsets are only unambiguous in a single-file store
+fn show_position(segmented: bool, position: Position) -> String {
+    if segmented {
+        format!("{}:{}", position.segment, position.offset)
+    } else {
+        position.offset.to_string()
+    }
+}
+
+fn show_change(segmented: bool, change: &Change, settings: &Settings) -> String {
+    let position = show_position(segmented, change.position());
+    let key = settings.output(&change.key);
+    match &change.op {
+        Op::Put { value, expires_at } => format!(
+            "{}: put {} -> {}{}",
+            position,
+            key,
+            settings.output(value),
+            show_expiry(*expires_at)
+        ),
+        Op::Delete => format!("{}: delete {}", position, key),
+    }
+}
+
+/// Prints the changes in the log from a given position, then waits for
+/// more unless `--no-follow` was given
+fn tail(path: &Path, matches: &ArgMatches, settings: &Settings) -> Result<(), Failure> {
+    let from = Position {
+        segment: *matches.get_one::<u64>("segment").unwrap(),
+        offset: *matches.get_one::<u64>("from").unwrap(),
+    };
+    let follow = !matches.get_flag("no-follow");
+
+    let mut tail = Tail::open(path, from)?;
+    let mut stdout = io::stdout();
+    loop {
+        match tail.next_change()? {
+            Some(change) => {
+                let line = show_change(tail.is_segmented(), &change, settings);
+                match writeln!(stdout, "{}", line) {
+                    // Whatever read the output has had enough, e.g. `head`
+                    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                    result => result?,
+                }
+            }
+            None if follow => thread::sleep(TAIL_POLL_INTERVAL),
+            None => return Ok(()),
+        }
+    }
+}
+
+fn format(matches: &ArgMatches) -> ExportFormat {
+    // clap has already checked it is one of the known formats
+    matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Reads commands from stdin until `exit` or the end of the input.
+/// A failing command is reported and the shell carries on.
+fn shell(store: &mut ActionKv, settings: &Settings) -> Result<(), Failure> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if !settings.quiet {
+            print!("kv> ");
+            io::stdout().flush()?;
+        }
+
+        let line = match lines.next() {
+            None => break,
+            Some(line) => line?,
+        };
+        let words = match split_words(&line) {
+            Ok(words) => words,
+            Err(message) => {
+                eprintln!("error: {}", message);
+                continue;
+            }
+        };
+        if words.is_empty() {
+            continue;
+        }
+
+        let matches = match shell_cli().try_get_matches_from(words) {
+            Ok(matches) => matches,
+            Err(err) => {
+                // Also covers `help`, which clap reports as an "error"
+                let _ = err.print();
+                continue;
+            }
+        };
+        let (action, sub_matches) = matches.subcommand().unwrap();
+        if action == "exit" {
+            break;
+        }
+        if let Err(failure) = execute(store, action, sub_matches, settings) {
+            failure.report();
+        }
+    }
+    Ok(())
+}
+
+/// Splits a shell line into words at whitespace.
+/// Double quotes group words with spaces; `\` escapes the next character.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quoted {
+        return Err("unterminated quote".to_string());
     }
+    words.extend(word);
+    Ok(words)
 }
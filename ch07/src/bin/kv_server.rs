@@ -0,0 +1,25 @@
+use std::net::TcpListener;
+
+use ch07::actionkv::{net, ActionKv, SharedKv};
+
+const USAGE: &str = "
+Usage:
+    kv_server FILE [ADDR]
+
+ADDR defaults to 127.0.0.1:7878
+";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let fname = args.get(1).expect(USAGE);
+    let addr = args.get(2).map(String::as_str).unwrap_or("127.0.0.1:7878");
+
+    let path = std::path::Path::new(&fname);
+    let mut store = ActionKv::open(path).expect("Unable to open file");
+    store.load().expect("Unable to load data");
+
+    let listener = TcpListener::bind(addr).expect("Unable to bind address");
+    println!("Serving {} on {}", fname, listener.local_addr().unwrap());
+
+    net::serve(listener, SharedKv::new(store)).expect("Server failed");
+}
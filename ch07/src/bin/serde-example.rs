@@ -19,7 +19,7 @@ fn main() {
     println!("Debug: {:?}", calabar);
 
     let as_json = serde_json::to_string(&calabar).unwrap();
-    println!("Json: {}, size: {}", as_json, as_json.as_bytes().len());
+    println!("Json: {}, size: {}", as_json, as_json.len());
 
     let as_bincode = bincode::serialize(&calabar).unwrap();
     println!("Bincode: {:02X?}, size: {}", as_bincode, as_bincode.len());
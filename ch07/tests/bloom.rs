@@ -0,0 +1,87 @@
+mod common;
+
+use std::fs;
+
+use ch07::actionkv::{ActionKv, KvOptions};
+use common::{loaded, temp_store};
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+fn with_filters() -> KvOptions {
+    KvOptions {
+        bloom_filter: Some(0.01),
+        ..KvOptions::default()
+    }
+}
+
+#[test]
+fn written_keys_are_never_ruled_out() {
+    let path = temp_store("bloom-written");
+    let mut store = loaded(&path, with_filters());
+    for i in 0..200u32 {
+        store.insert(&i.to_le_bytes(), b"v").unwrap();
+    }
+    store.close().unwrap();
+
+    let store = loaded(&path, with_filters());
+    assert!((0..200u32).all(|i| store.may_contain(&i.to_le_bytes())));
+    let absent = (1000..2000u32)
+        .filter(|i| !store.may_contain(&i.to_le_bytes()))
+        .count();
+    assert!(
+        absent > 900,
+        "only {} of 1000 absent keys ruled out",
+        absent
+    );
+
+    let bloom = store.stats().unwrap().bloom.unwrap();
+    assert_eq!(bloom.negatives, absent as u64);
+    assert_eq!(bloom.false_positives, 1000 - absent as u64);
+}
+
+#[test]
+fn keys_may_be_present_before_loading() {
+    let path = temp_store("bloom-unloaded");
+    let mut store = loaded(&path, with_filters());
+    store.insert(b"a", b"1").unwrap();
+    store.close().unwrap();
+
+    let store = ActionKv::open_with(&path, with_filters()).unwrap();
+    assert!(store.may_contain(b"a"));
+    assert!(store.may_contain(b"never written"));
+}
+
+#[test]
+fn deleted_keys_count_as_false_positives_until_compaction() {
+    let path = temp_store("bloom-deleted");
+    let mut store = loaded(&path, with_filters());
+    store.insert(b"a", b"1").unwrap();
+    store.delete(b"a").unwrap();
+
+    assert!(store.may_contain(b"a"));
+    assert_eq!(store.stats().unwrap().bloom.unwrap().false_positives, 1);
+
+    store.compact().unwrap();
+    assert!(!store.may_contain(b"a"));
+}
+
+#[test]
+fn damaged_filters_are_rebuilt() {
+    let path = temp_store("bloom-damaged");
+    let mut store = loaded(&path, with_filters());
+    store.insert(b"a", b"1").unwrap();
+    store.close().unwrap();
+
+    // A word count whose size in bytes overflows, under a valid checksum
+    let bloom_path = path.with_extension("akv.bloom");
+    let mut data = fs::read(&bloom_path).unwrap();
+    // After the magic, data length, capacity, key count and hash count
+    let count_at = 8 + 8 + 8 + 8 + 4;
+    data[count_at..count_at + 8].copy_from_slice(&(1u64 << 61).to_le_bytes());
+    let body = data.len() - 4;
+    let checksum = Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(&data[..body]);
+    data[body..].copy_from_slice(&checksum.to_le_bytes());
+    fs::write(&bloom_path, &data).unwrap();
+
+    let store = loaded(&path, with_filters());
+    assert!(store.may_contain(b"a"));
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use ch07::actionkv::{ActionKv, Change, KvOptions, Op, Position, Tail, WriteBatch};
+use common::{loaded, temp_store};
+
+const START: Position = Position {
+    segment: 0,
+    offset: 0,
+};
+
+fn put(key: &[u8], value: &[u8]) -> (Vec<u8>, Op) {
+    let op = Op::Put {
+        value: value.to_vec(),
+        expires_at: None,
+    };
+    (key.to_vec(), op)
+}
+
+fn delete(key: &[u8]) -> (Vec<u8>, Op) {
+    (key.to_vec(), Op::Delete)
+}
+
+/// Every change the tail can read for now
+fn drain(tail: &mut Tail) -> Vec<(Vec<u8>, Op)> {
+    let mut changes = Vec::new();
+    while let Some(Change { key, op, .. }) = tail.next_change().unwrap() {
+        changes.push((key, op));
+    }
+    changes
+}
+
+#[test]
+fn subscribers_see_every_write() {
+    let path = temp_store("change-subscribe");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"before", b"0").unwrap();
+    let changes = store.subscribe();
+
+    store.insert(b"a", b"1").unwrap();
+    store.delete(b"a").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", b"2").delete(b"before");
+    store.write_batch(batch).unwrap();
+
+    let received: Vec<_> = changes.try_iter().map(|c| (c.key, c.op)).collect();
+    assert_eq!(
+        received,
+        [
+            put(b"a", b"1"),
+            delete(b"a"),
+            put(b"b", b"2"),
+            delete(b"before")
+        ]
+    );
+}
+
+#[test]
+fn tail_follows_a_writer() {
+    let path = temp_store("change-tail");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+
+    let mut tail = Tail::open(&path, START).unwrap();
+    assert_eq!(drain(&mut tail), [put(b"a", b"1")]);
+    assert_eq!(drain(&mut tail), []);
+
+    store.delete(b"a").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", b"2").put(b"c", b"3");
+    store.write_batch(batch).unwrap();
+    assert_eq!(
+        drain(&mut tail),
+        [delete(b"a"), put(b"b", b"2"), put(b"c", b"3")]
+    );
+}
+
+#[test]
+fn tail_starts_from_a_position() {
+    let path = temp_store("change-tail-from");
+    let mut store = loaded(&path, KvOptions::default());
+    let changes = store.subscribe();
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"2").unwrap();
+    let second = changes.try_iter().nth(1).unwrap();
+
+    let mut tail = Tail::open(&path, second.position()).unwrap();
+    assert_eq!(drain(&mut tail), [put(b"b", b"2")]);
+}
+
+#[test]
+fn tail_follows_a_compacted_file() {
+    let path = temp_store("change-tail-compact");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"a", b"2").unwrap();
+
+    let mut tail = Tail::open(&path, START).unwrap();
+    assert_eq!(drain(&mut tail), [put(b"a", b"1"), put(b"a", b"2")]);
+
+    store.compact().unwrap();
+    store.insert(b"b", b"3").unwrap();
+    // The new file is read from its start
+    assert_eq!(drain(&mut tail), [put(b"a", b"2"), put(b"b", b"3")]);
+    store.insert(b"c", b"4").unwrap();
+    assert_eq!(drain(&mut tail), [put(b"c", b"4")]);
+}
+
+#[test]
+fn tail_rolls_over_to_new_segments() {
+    let path = temp_store("change-tail-segments");
+    let options = KvOptions {
+        segment_size: Some(64),
+        ..KvOptions::default()
+    };
+    let mut store = loaded(&path, options);
+    let mut tail = Tail::open(&path, START).unwrap();
+    assert!(tail.is_segmented());
+
+    let expected: Vec<_> = (0..10u8).map(|i| put(&[b'k', i], &[i; 16])).collect();
+    for (key, _) in &expected {
+        store.insert(key, &[key[1]; 16]).unwrap();
+    }
+    assert_eq!(drain(&mut tail), expected);
+    assert!(tail.position().segment > 0);
+}
+
+#[test]
+fn tail_keeps_its_position_when_a_batch_is_dropped() {
+    let path = temp_store("change-tail-dropped-batch");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"b", &[2; 64]).put(b"c", &[3; 64]);
+    store.write_batch(batch).unwrap();
+    drop(store);
+
+    // The commit record is torn, as if the writer crashed while appending it
+    let len = std::fs::metadata(&path).unwrap().len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 1)
+        .unwrap();
+
+    let mut tail = Tail::open(&path, START).unwrap();
+    assert_eq!(drain(&mut tail), [put(b"a", b"1")]);
+
+    // The next write cuts the file back to where the batch started
+    let mut store = ActionKv::open(&path).unwrap();
+    assert!(store.recover().unwrap().is_some());
+    store.insert(b"d", b"4").unwrap();
+    assert_eq!(drain(&mut tail), [put(b"d", b"4")]);
+    store.insert(b"e", b"5").unwrap();
+    assert_eq!(drain(&mut tail), [put(b"e", b"5")]);
+}
@@ -0,0 +1,27 @@
+// Each test crate only uses some of these helpers
+#![allow(dead_code)]
+
+pub mod model;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ch07::actionkv::{ActionKv, KvOptions};
+
+/// Returns a path for a store file in a fresh temporary directory.
+/// Each test should use its own `name`, since tests run in parallel.
+pub fn temp_store(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("actionkv-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("store.akv")
+}
+
+/// Opens a store and loads its index
+pub fn loaded(path: &Path, options: KvOptions) -> ActionKv {
+    let mut store = ActionKv::open_with(path, options).unwrap();
+    store.load().unwrap();
+    store
+}
@@ -0,0 +1,119 @@
+//! Random operations on a store, checked against a `HashMap` holding
+//! what the store should contain.
+
+use std::collections::HashMap;
+
+use ch07::actionkv::{ActionKv, WriteBatch};
+use rand::{rngs::StdRng, Rng};
+
+/// What a store should contain
+pub type Model = HashMap<Vec<u8>, Vec<u8>>;
+
+/// A write, as applied to both the store and the model
+#[derive(Debug, Clone)]
+pub enum Op {
+    Insert(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+
+    /// Puts, or deletes where there is no value
+    Batch(Vec<(Vec<u8>, Option<Vec<u8>>)>),
+}
+
+/// Keys are drawn from a small set, so that most writes overwrite or delete
+/// an existing key
+const KEYS: usize = 24;
+
+pub fn random_key(rng: &mut StdRng) -> Vec<u8> {
+    format!("key-{}", rng.gen_range(0..KEYS)).into_bytes()
+}
+
+/// Random bytes, sometimes empty: an empty value is not a deletion.
+/// Some values are repetitive, so that compression has something to do.
+pub fn random_value(rng: &mut StdRng) -> Vec<u8> {
+    let len = rng.gen_range(0..64);
+    if rng.gen_bool(0.3) {
+        vec![rng.gen(); len * 4]
+    } else {
+        (0..len).map(|_| rng.gen()).collect()
+    }
+}
+
+pub fn random_op(rng: &mut StdRng) -> Op {
+    match rng.gen_range(0..10) {
+        0..=5 => Op::Insert(random_key(rng), random_value(rng)),
+        6..=7 => Op::Delete(random_key(rng)),
+        _ => {
+            let len = rng.gen_range(1..6);
+            let writes = (0..len)
+                .map(|_| {
+                    let key = random_key(rng);
+                    let value = rng.gen_bool(0.7).then(|| random_value(rng));
+                    (key, value)
+                })
+                .collect();
+            Op::Batch(writes)
+        }
+    }
+}
+
+pub fn apply(store: &mut ActionKv, model: &mut Model, op: &Op) {
+    match op {
+        Op::Insert(key, value) => {
+            store.insert(key, value).unwrap();
+            model.insert(key.clone(), value.clone());
+        }
+        Op::Delete(key) => {
+            store.delete(key).unwrap();
+            model.remove(key);
+        }
+        Op::Batch(writes) => {
+            let mut batch = WriteBatch::new();
+            for (key, value) in writes {
+                match value {
+                    Some(value) => {
+                        batch.put(key, value);
+                        model.insert(key.clone(), value.clone());
+                    }
+                    None => {
+                        batch.delete(key);
+                        model.remove(key);
+                    }
+                }
+            }
+            store.write_batch(batch).unwrap();
+        }
+    }
+}
+
+/// Everything a loaded store contains, read through its iterator
+pub fn contents(store: &ActionKv) -> Model {
+    store
+        .iter()
+        .map(|pair| {
+            let pair = pair.unwrap();
+            (pair.key, pair.value)
+        })
+        .collect()
+}
+
+/// Checks the store against the model, key by key and as a whole
+pub fn assert_matches(store: &ActionKv, model: &Model, context: &str) {
+    for key in 0..KEYS {
+        let key = format!("key-{}", key).into_bytes();
+        assert_eq!(
+            store.get(&key).unwrap().as_ref(),
+            model.get(&key),
+            "{}: value of {}",
+            context,
+            String::from_utf8_lossy(&key)
+        );
+        if model.contains_key(&key) {
+            assert!(
+                store.may_contain(&key),
+                "{}: Bloom filter lost a key",
+                context
+            );
+        }
+    }
+    assert_eq!(&contents(store), model, "{}: contents", context);
+}
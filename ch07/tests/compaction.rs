@@ -0,0 +1,142 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use ch07::actionkv::{ActionKv, KvOptions};
+use common::{loaded, temp_store};
+
+/// Names of the segment files of a store, oldest first
+fn segment_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".seg"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn compaction_keeps_live_records_only() {
+    let path = temp_store("compact-live");
+    let mut store = loaded(&path, KvOptions::default());
+    for i in 0..100u32 {
+        store.insert(b"counter", &i.to_le_bytes()).unwrap();
+    }
+    store.insert(b"gone", b"x").unwrap();
+    store.delete(b"gone").unwrap();
+    let before = std::fs::metadata(&path).unwrap().len();
+
+    store.compact().unwrap();
+    assert!(std::fs::metadata(&path).unwrap().len() < before);
+    assert_eq!(
+        store.get(b"counter").unwrap(),
+        Some(99u32.to_le_bytes().to_vec())
+    );
+    store.close().unwrap();
+
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.index.len(), 1);
+    assert_eq!(store.get(b"gone").unwrap(), None);
+}
+
+#[test]
+fn compacting_without_loading_keeps_every_key() {
+    let path = temp_store("compact-unloaded");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    store.close().unwrap();
+
+    let mut store = ActionKv::open(&path).unwrap();
+    store.insert(b"b", b"2").unwrap();
+    store.compact().unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    store.close().unwrap();
+
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn compacting_segments_without_loading_keeps_every_key() {
+    let path = temp_store("compact-unloaded-segments");
+    let options = KvOptions {
+        segment_size: Some(64),
+        ..KvOptions::default()
+    };
+    let mut store = loaded(&path, options.clone());
+    for i in 0..10u8 {
+        store.insert(&[b'k', i], &[i; 16]).unwrap();
+    }
+    store.close().unwrap();
+
+    let mut store = ActionKv::open_with(&path, options.clone()).unwrap();
+    store.compact().unwrap();
+    store.close().unwrap();
+
+    let store = loaded(&path, options);
+    for i in 0..10u8 {
+        assert_eq!(store.get(&[b'k', i]).unwrap(), Some(vec![i; 16]));
+    }
+}
+
+#[test]
+fn segment_size_is_remembered() {
+    let path = temp_store("compact-segment-size");
+    let options = KvOptions {
+        segment_size: Some(64),
+        ..KvOptions::default()
+    };
+    loaded(&path, options).close().unwrap();
+
+    let mut store = loaded(&path, KvOptions::default());
+    for i in 0..10u8 {
+        store.insert(&[b'k', i], &[i; 16]).unwrap();
+    }
+    store.close().unwrap();
+    assert!(segment_files(&path).len() > 1);
+}
+
+#[test]
+fn interrupted_install_is_finished_on_open() {
+    let path = temp_store("compact-interrupted");
+    let options = KvOptions {
+        segment_size: Some(64),
+        ..KvOptions::default()
+    };
+    let mut store = loaded(&path, options.clone());
+    for i in 0..10u8 {
+        store.insert(&[b'k', i], &[i; 16]).unwrap();
+    }
+    store.insert(b"k0", b"new").unwrap();
+    store.close().unwrap();
+    let sources: Vec<_> = segment_files(&path)
+        .into_iter()
+        .map(|name| (fs::read(path.join(&name)).unwrap(), name))
+        .collect();
+
+    let mut store = loaded(&path, options.clone());
+    store.compact().unwrap();
+    store.close().unwrap();
+    let compacted = segment_files(&path);
+    let target = &compacted[0];
+
+    // A crash once the compaction was marked as done, before any source was deleted
+    let target_path = path.join(target);
+    fs::rename(&target_path, target_path.with_extension("compacted")).unwrap();
+    for (contents, name) in &sources {
+        if name <= target {
+            fs::write(path.join(name), contents).unwrap();
+        }
+    }
+    fs::write(path.join("99999999.compact"), b"incomplete").unwrap();
+
+    let store = loaded(&path, options);
+    assert_eq!(segment_files(&path), compacted);
+    assert!(!path.join("99999999.compact").exists());
+    assert_eq!(store.get(b"k0").unwrap(), Some(b"new".to_vec()));
+    for i in 1..10u8 {
+        assert_eq!(store.get(&[b'k', i]).unwrap(), Some(vec![i; 16]));
+    }
+}
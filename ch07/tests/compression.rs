@@ -0,0 +1,62 @@
+mod common;
+
+use std::fs;
+
+use ch07::actionkv::{Compression, KvOptions};
+use common::{loaded, temp_store};
+
+/// Offset of the kind byte of the first record: after the file header and its checksum
+const FIRST_KIND: usize = 16 + 4;
+
+fn lz4() -> KvOptions {
+    KvOptions {
+        compression: Compression::Lz4,
+        ..KvOptions::default()
+    }
+}
+
+#[test]
+fn compressed_values_read_back() {
+    let path = temp_store("compression-round-trip");
+    let value = b"abcd".repeat(1024);
+    let mut store = loaded(&path, lz4());
+    store.insert(b"big", &value).unwrap();
+    store.insert(b"small", b"xyz").unwrap();
+    assert_eq!(store.get(b"big").unwrap(), Some(value.clone()));
+    store.close().unwrap();
+
+    let data = fs::read(&path).unwrap();
+    assert_ne!(data[FIRST_KIND] & 0x80, 0, "the first value is compressed");
+    assert!(data.len() < value.len());
+
+    // Each record says whether it is compressed, whatever the handle writes
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"plain", &value).unwrap();
+    assert_eq!(store.get(b"big").unwrap(), Some(value.clone()));
+    assert_eq!(store.get(b"small").unwrap(), Some(b"xyz".to_vec()));
+    assert_eq!(store.get(b"plain").unwrap(), Some(value));
+}
+
+#[test]
+fn stats_show_the_compression_ratio() {
+    let path = temp_store("compression-stats");
+    let value = b"abcd".repeat(1024);
+    let mut store = loaded(&path, lz4());
+    store.insert(b"k1", &value).unwrap();
+    store.insert(b"k2", &value).unwrap();
+
+    let stats = store.stats().unwrap();
+    assert_eq!(stats.keys, 2);
+    assert_eq!(stats.logical_bytes, 2 * (2 + value.len() as u64));
+    assert!(stats.live_bytes < stats.logical_bytes / 10, "{:?}", stats);
+    assert!(stats.live_bytes <= stats.file_bytes);
+
+    // Values that don't shrink are stored as they are, so the ratio is about 1
+    let path = temp_store("compression-stats-incompressible");
+    let mut store = loaded(&path, lz4());
+    store.insert(b"k1", b"xyz").unwrap();
+    let stats = store.stats().unwrap();
+    assert_eq!(stats.logical_bytes, 5);
+    assert!(stats.live_bytes > stats.logical_bytes);
+    assert_eq!(fs::read(&path).unwrap()[FIRST_KIND] & 0x80, 0);
+}
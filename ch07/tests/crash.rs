@@ -0,0 +1,142 @@
+//! Damages a log the way a crash or a bad disk would, and checks that
+//! what can be read back is the result of some prefix of the operations
+//! that were written, never a mix of old and new values.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use ch07::actionkv::{ActionKv, KvError};
+use common::model::{self, Model};
+use common::temp_store;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const OPS: usize = 60;
+
+/// A log written by random operations
+struct Log {
+    data: Vec<u8>,
+
+    /// File length after each operation, starting with the empty store
+    ends: Vec<u64>,
+
+    /// Expected contents after each operation, starting with the empty store
+    states: Vec<Model>,
+}
+
+fn write_log(name: &str, seed: u64) -> Log {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let path = temp_store(name);
+    let mut store = ActionKv::open(&path).unwrap();
+    store.load().unwrap();
+
+    let mut model = Model::new();
+    let mut ends = vec![fs::metadata(&path).unwrap().len()];
+    let mut states = vec![model.clone()];
+    for _ in 0..OPS {
+        let op = model::random_op(&mut rng);
+        model::apply(&mut store, &mut model, &op);
+        ends.push(fs::metadata(&path).unwrap().len());
+        states.push(model.clone());
+    }
+
+    // Not closed: there is no hint file to read the index from
+    drop(store);
+    Log {
+        data: fs::read(&path).unwrap(),
+        ends,
+        states,
+    }
+}
+
+/// Opens a damaged store the way an application restarting after a crash
+/// would: `load`, then `recover` if the log has a damaged tail
+fn reopen(path: &Path) -> Result<ActionKv, KvError> {
+    let mut store = ActionKv::open(path)?;
+    match store.load() {
+        Ok(()) => Ok(store),
+        Err(KvError::TornRecord { .. }) | Err(KvError::Corruption { .. }) => {
+            store.recover()?;
+            Ok(store)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[test]
+fn truncation_recovers_the_operations_written_before_the_cut() {
+    for seed in 0..2 {
+        let log = write_log(&format!("truncate-source-{}", seed), seed);
+        let path = temp_store(&format!("truncate-{}", seed));
+
+        for cut in 0..=log.data.len() {
+            fs::write(&path, &log.data[..cut]).unwrap();
+            let context = format!("seed {}, cut at {}", seed, cut);
+            let store = reopen(&path).unwrap_or_else(|err| panic!("{}: {}", context, err));
+
+            // Exactly the operations that were completely written survive
+            let written = log.ends.iter().filter(|&&end| end <= cut as u64).count();
+            let expected = &log.states[written.max(1) - 1];
+            model::assert_matches(&store, expected, &context);
+        }
+    }
+}
+
+#[test]
+fn writes_after_recovery_survive_a_reopen() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let log = write_log("append-source", 0);
+    let path = temp_store("append");
+
+    for _ in 0..100 {
+        let cut = rng.gen_range(0..=log.data.len());
+        fs::write(&path, &log.data[..cut]).unwrap();
+        let context = format!("cut at {}", cut);
+
+        let mut store = reopen(&path).unwrap();
+        let written = log.ends.iter().filter(|&&end| end <= cut as u64).count();
+        let mut expected = log.states[written.max(1) - 1].clone();
+        let op = model::random_op(&mut rng);
+        model::apply(&mut store, &mut expected, &op);
+        store.close().unwrap();
+
+        let mut store = ActionKv::open(&path).unwrap();
+        store.load().unwrap();
+        model::assert_matches(&store, &expected, &context);
+    }
+}
+
+#[test]
+fn bit_flips_are_detected_or_lose_only_the_damaged_tail() {
+    for seed in 0..2 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let log = write_log(&format!("flip-source-{}", seed), seed);
+        let path = temp_store(&format!("flip-{}", seed));
+
+        for offset in 0..log.data.len() {
+            let mut data = log.data.clone();
+            let bit = rng.gen_range(0..8);
+            data[offset] ^= 1 << bit;
+            fs::write(&path, &data).unwrap();
+            let context = format!("seed {}, bit {} of byte {} flipped", seed, bit, offset);
+
+            // Damage can be refused, but whatever is read must be a state
+            // the store was really in
+            let store = match reopen(&path) {
+                Ok(store) => store,
+                Err(_) => continue,
+            };
+            let contents = model::contents(&store);
+            let prefix = log.states.iter().rposition(|state| *state == contents);
+            let prefix = prefix.unwrap_or_else(|| panic!("{}: not a prefix", context));
+
+            // Only the operation holding the flipped byte can be lost
+            let damaged = log.ends.iter().filter(|&&end| end <= offset as u64).count();
+            assert!(
+                prefix + 1 >= damaged,
+                "{}: lost more than the tail",
+                context
+            );
+        }
+    }
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use ch07::actionkv::{export, ActionKv, ExportFormat, KvOptions};
+use common::{loaded, temp_store};
+
+fn contents(store: &ActionKv) -> Vec<(Vec<u8>, Vec<u8>)> {
+    store
+        .iter()
+        .map(|kv| {
+            let kv = kv.unwrap();
+            (kv.key, kv.value)
+        })
+        .collect()
+}
+
+/// A store with text, binary, overwritten and deleted keys
+fn source(name: &str) -> ActionKv {
+    let mut store = loaded(&temp_store(name), KvOptions::default());
+    store.insert(b"text", b"plain value").unwrap();
+    store.insert(b"quoted", b"a \"b\", c\nd").unwrap();
+    store.insert(b"empty", b"").unwrap();
+    store
+        .insert(b"binary value", &[0, 159, 146, 150, 255])
+        .unwrap();
+    store.insert(&[0xFF, 0xFE, 0x00], b"binary key").unwrap();
+    store.insert(b"overwritten", b"old").unwrap();
+    store.insert(b"overwritten", b"new").unwrap();
+    store.insert(b"deleted", b"gone").unwrap();
+    store.delete(b"deleted").unwrap();
+    store.insert(&[0x80], &[0x81]).unwrap();
+    store.delete(&[0x80]).unwrap();
+    store
+}
+
+fn round_trip(format: ExportFormat, name: &str) {
+    let store = source(&format!("{}-source", name));
+    let mut exported = Vec::new();
+    let count = export::export(&store, format, &mut exported).unwrap();
+    assert_eq!(count, 6);
+    // Tombstoned keys aren't exported
+    let text = String::from_utf8(exported.clone()).unwrap();
+    assert!(!text.contains("deleted") && !text.contains("gone"));
+    assert!(!text.contains("old"));
+
+    let mut imported = loaded(
+        &temp_store(&format!("{}-target", name)),
+        KvOptions::default(),
+    );
+    let count = export::import(&mut imported, format, exported.as_slice()).unwrap();
+    assert_eq!(count, 6);
+    assert_eq!(contents(&imported), contents(&store));
+    assert_eq!(imported.get(b"deleted").unwrap(), None);
+    assert_eq!(imported.get(&[0x80]).unwrap(), None);
+}
+
+#[test]
+fn jsonl_round_trip() {
+    round_trip(ExportFormat::Jsonl, "export-jsonl");
+}
+
+#[test]
+fn csv_round_trip() {
+    round_trip(ExportFormat::Csv, "export-csv");
+}
+
+#[test]
+fn import_is_all_or_nothing() {
+    let mut store = loaded(&temp_store("export-bad-import"), KvOptions::default());
+    let input = "{\"key\":\"a\",\"value\":\"1\"}\nnot json\n";
+    assert!(export::import(&mut store, ExportFormat::Jsonl, input.as_bytes()).is_err());
+    assert_eq!(store.get(b"a").unwrap(), None);
+}
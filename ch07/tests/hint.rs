@@ -0,0 +1,63 @@
+mod common;
+
+use ch07::actionkv::{ActionKv, KvOptions};
+use common::{loaded, temp_store};
+
+#[test]
+fn hint_is_read_on_reopen() {
+    let path = temp_store("hint-reopen");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"2").unwrap();
+    store.close().unwrap();
+
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"c", b"3").unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    store.close().unwrap();
+
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.index.len(), 3);
+}
+
+#[test]
+fn closing_without_loading_keeps_every_key() {
+    let path = temp_store("hint-unloaded");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"2").unwrap();
+    store.close().unwrap();
+
+    // Written to without reading the index: it only knows about c
+    let mut store = ActionKv::open(&path).unwrap();
+    store.insert(b"c", b"3").unwrap();
+    store.close().unwrap();
+
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn hint_for_a_replaced_data_file_is_ignored() {
+    let path = temp_store("hint-replaced");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"2").unwrap();
+    store.close().unwrap();
+
+    // A different file, at least as long, copied over the one the hint was written for
+    let other = temp_store("hint-replacement");
+    let mut store = loaded(&other, KvOptions::default());
+    store.insert(b"x", b"the first value").unwrap();
+    store.insert(b"y", b"the second value").unwrap();
+    store.close().unwrap();
+    std::fs::copy(&other, &path).unwrap();
+
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.index.len(), 2);
+    assert_eq!(store.get(b"a").unwrap(), None);
+    assert_eq!(store.get(b"x").unwrap(), Some(b"the first value".to_vec()));
+    assert_eq!(store.get(b"y").unwrap(), Some(b"the second value".to_vec()));
+}
@@ -0,0 +1,272 @@
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use ch07::actionkv::{Compression, KvOptions};
+use common::{loaded, temp_store};
+
+/// Runs kv_mem on a store, returning its exit code, stdout and stderr
+fn run(store: &Path, args: &[&str]) -> (i32, String, String) {
+    run_with_input(store, args, "")
+}
+
+/// Runs kv_mem on a store with `input` on stdin
+fn run_with_input(store: &Path, args: &[&str], input: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kv_mem"))
+        .arg(store)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+/// Runs kv_mem on a store, returning its output once it has succeeded
+fn kv_mem(store: &Path, args: &[&str]) -> String {
+    let (code, stdout, stderr) = run(store, args);
+    assert_eq!(code, 0, "kv_mem {:?} failed: {}", args, stderr);
+    stdout
+}
+
+#[test]
+fn export_prints_only_the_data() {
+    let path = temp_store("kv-mem-export");
+    kv_mem(&path, &["insert", "a", "1"]);
+    kv_mem(&path, &["insert", "b", "2"]);
+    assert!(kv_mem(&path, &["get", "a"]).contains("Index map:"));
+
+    let exported = kv_mem(&path, &["export", "--format", "jsonl"]);
+    let lines: Vec<serde_json::Value> = exported
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["key"], "a");
+    assert_eq!(lines[1]["key"], "b");
+}
+
+#[test]
+fn reading_at_a_compacted_position_is_an_error() {
+    let path = temp_store("kv-mem-at-offset");
+    for i in 0..6 {
+        let key = format!("k{}", i);
+        kv_mem(
+            &path,
+            &[
+                "-q",
+                "--segment-size",
+                "64",
+                "insert",
+                &key,
+                "0123456789abcdef",
+            ],
+        );
+    }
+    let at = ["-q", "get", "k0", "--at-offset", "16", "--segment", "1"];
+    assert_eq!(kv_mem(&path, &at), "GET: k0 -> 0123456789abcdef\n");
+
+    kv_mem(&path, &["-q", "compact"]);
+    let (code, stdout, stderr) = run(&path, &at);
+    assert_eq!(code, 3);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("not in the log"), "{}", stderr);
+}
+
+#[test]
+fn stat_shows_the_observed_false_positive_rate() {
+    let path = temp_store("kv-mem-bloom");
+    let bloom = ["-q", "--bloom-filter", "0.01"];
+    kv_mem(&path, &[&bloom[..], &["insert", "a", "1"]].concat());
+    let stat = kv_mem(&path, &[&bloom[..], &["stat"]].concat());
+    assert!(stat.contains("no lookups of absent keys yet"), "{}", stat);
+
+    let (code, stdout, _) = run_with_input(
+        &path,
+        &[&bloom[..], &["shell"]].concat(),
+        "get b\nget c\nget a\nstat\n",
+    );
+    assert_eq!(code, 0);
+    assert!(
+        stdout.contains("0.0000% observed (0 of 2 lookups of absent keys)"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn verify_reports_damage_in_its_exit_code() {
+    let path = temp_store("kv-mem-verify");
+    for key in ["k1", "k2", "k3"] {
+        kv_mem(&path, &["-q", "insert", key, "some value"]);
+    }
+    assert_eq!(kv_mem(&path, &["verify"]), "3 records checked, 0 bad\n");
+
+    // The last byte of the last value
+    let mut data = fs::read(&path).unwrap();
+    *data.last_mut().unwrap() ^= 0x01;
+    fs::write(&path, &data).unwrap();
+    let (code, stdout, _) = run(&path, &["verify"]);
+    assert_eq!(code, 1);
+    assert!(
+        stdout.starts_with("Data corruption at offset"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.ends_with("3 records checked, 1 bad\n"), "{}", stdout);
+
+    fs::write(&path, &data[..data.len() - 3]).unwrap();
+    let (code, stdout, _) = run(&path, &["verify"]);
+    assert_eq!(code, 1);
+    assert!(
+        stdout.starts_with("Incomplete record at offset"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn stat_shows_the_compression_ratio() {
+    let path = temp_store("kv-mem-compression");
+    let options = KvOptions {
+        compression: Compression::Lz4,
+        ..KvOptions::default()
+    };
+    let mut store = loaded(&path, options);
+    store.insert(b"k", &b"abcd".repeat(1024)).unwrap();
+    store.close().unwrap();
+
+    let stat = kv_mem(&path, &["stat"]);
+    assert!(stat.contains("live logical:  4097 bytes"), "{}", stat);
+    let ratio: f64 = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("ratio:"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert!(ratio > 10.0, "{}", stat);
+}
+
+#[test]
+fn exit_codes() {
+    let path = temp_store("kv-mem-exit-codes");
+    assert_eq!(run(&path, &["-q", "insert", "a", "1"]).0, 0);
+    assert_eq!(kv_mem(&path, &["-q", "get", "a"]), "GET: a -> 1\n");
+
+    let (code, stdout, stderr) = run(&path, &["-q", "get", "missing"]);
+    assert_eq!((code, stdout.as_str()), (1, ""));
+    assert_eq!(stderr, "missing not found\n");
+
+    // Arguments clap rejects, and a value that isn't valid hex
+    assert_eq!(run(&path, &["-q", "insert", "a"]).0, 2);
+    assert_eq!(run(&path, &["-q", "frobnicate"]).0, 2);
+    let (code, _, stderr) = run(&path, &["-q", "--hex", "get", "zz"]);
+    assert_eq!(code, 2);
+    assert!(stderr.starts_with("error: invalid hex"), "{}", stderr);
+
+    let unsupported = temp_store("kv-mem-exit-codes-unsupported");
+    fs::write(&unsupported, b"ACTIONKV\x63\x00\x00\x00\x00\x00\x00\x00").unwrap();
+    let (code, _, stderr) = run(&unsupported, &["-q", "get", "a"]);
+    assert_eq!(code, 3);
+    assert!(
+        stderr.contains("Unsupported format version 99"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn hex_keys_and_values() {
+    let path = temp_store("kv-mem-hex");
+    kv_mem(&path, &["-q", "--hex", "insert", "00ff", "0a0d00"]);
+    assert_eq!(
+        kv_mem(&path, &["-q", "--hex", "get", "00ff"]),
+        "GET: 00ff -> 0a0d00\n"
+    );
+    assert_eq!(
+        kv_mem(&path, &["-q", "--hex", "list", "00"]),
+        "00ff -> 0a0d00\n"
+    );
+    kv_mem(&path, &["-q", "insert", "text", "hi"]);
+    assert_eq!(
+        kv_mem(&path, &["-q", "--hex", "get", "74657874"]),
+        "GET: 74657874 -> 6869\n"
+    );
+}
+
+#[test]
+fn values_from_a_file() {
+    let path = temp_store("kv-mem-file");
+    let value_path = temp_store("kv-mem-file-value");
+    fs::write(&value_path, b"line 1\nline 2\n\x00\xff").unwrap();
+    let value_arg = value_path.to_str().unwrap();
+
+    kv_mem(&path, &["-q", "insert", "k", "--file", value_arg]);
+    assert_eq!(
+        kv_mem(&path, &["-q", "--hex", "get", "6b"]),
+        format!("GET: 6b -> {}\n", hex::encode(b"line 1\nline 2\n\x00\xff"))
+    );
+
+    let (code, _, _) = run(&path, &["-q", "insert", "k", "v", "--file", value_arg]);
+    assert_eq!(code, 2);
+    let (code, _, _) = run(&path, &["-q", "insert", "k", "--file", "/nonexistent"]);
+    assert_eq!(code, 3);
+}
+
+#[test]
+fn shell_runs_commands_until_exit() {
+    let path = temp_store("kv-mem-shell");
+    let input = concat!(
+        "insert \"two words\" \"a \\\"quoted\\\" value\"\n",
+        "get \"two words\"\n",
+        "insert back\\ slash x\\\\y\n",
+        "get \"back slash\"\n",
+        "\n",
+        "insert open \"quote\n",
+        "get missing\n",
+        "frobnicate\n",
+        "insert k \"\"\n",
+        "list k\n",
+        "exit\n",
+        "insert after exit\n",
+    );
+    let (code, stdout, stderr) = run_with_input(&path, &["-q", "shell"], input);
+    assert_eq!(code, 0, "{}", stderr);
+    assert_eq!(
+        stdout,
+        concat!(
+            "GET: two words -> a \"quoted\" value\n",
+            "GET: back slash -> x\\y\n",
+            "k -> \n",
+        )
+    );
+    assert!(stderr.contains("error: unterminated quote"), "{}", stderr);
+    assert!(stderr.contains("missing not found"), "{}", stderr);
+    assert!(stderr.contains("frobnicate"), "{}", stderr);
+
+    // What the shell wrote is kept, and nothing after `exit` ran
+    assert_eq!(
+        kv_mem(&path, &["-q", "get", "two words"]),
+        "GET: two words -> a \"quoted\" value\n"
+    );
+    assert_eq!(run(&path, &["-q", "get", "after"]).0, 1);
+    assert_eq!(run(&path, &["-q", "get", "open"]).0, 1);
+}
@@ -0,0 +1,82 @@
+//! Stores written before the file header was introduced: records with no
+//! kind, where an empty value meant the key was deleted.
+
+mod common;
+
+use std::fs;
+
+use ch07::actionkv::KvOptions;
+use common::{loaded, temp_store};
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+/// A record in the headerless format: checksum, key and value lengths, key, value
+fn legacy_record(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let data = [key, value].concat();
+    let mut record = Vec::new();
+    record.extend(
+        Crc::<u32>::new(&CRC_32_ISO_HDLC)
+            .checksum(&data)
+            .to_le_bytes(),
+    );
+    record.extend((key.len() as u32).to_le_bytes());
+    record.extend((value.len() as u32).to_le_bytes());
+    record.extend(data);
+    record
+}
+
+/// A legacy store where a was updated, b deleted and c left alone
+fn legacy_store(name: &str) -> std::path::PathBuf {
+    let path = temp_store(name);
+    let records = [
+        legacy_record(b"a", b"1"),
+        legacy_record(b"b", b"2"),
+        legacy_record(b"c", b"3"),
+        legacy_record(b"a", b"4"),
+        legacy_record(b"b", b""),
+    ];
+    fs::write(&path, records.concat()).unwrap();
+    path
+}
+
+#[test]
+fn legacy_store_is_readable() {
+    let store = loaded(&legacy_store("legacy-read"), KvOptions::default());
+    assert_eq!(store.get(b"a").unwrap(), Some(b"4".to_vec()));
+    assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn empty_legacy_values_are_deletions() {
+    let store = loaded(&legacy_store("legacy-tombstone"), KvOptions::default());
+    assert_eq!(store.get(b"b").unwrap(), None);
+    assert_eq!(store.index.len(), 2);
+}
+
+#[test]
+fn legacy_store_refuses_writes() {
+    let path = legacy_store("legacy-write");
+    let before = fs::read(&path).unwrap();
+    let mut store = loaded(&path, KvOptions::default());
+
+    let err = store.insert(b"d", b"5").unwrap_err();
+    assert!(err.to_string().contains("compact it to upgrade"), "{}", err);
+    assert!(store.delete(b"a").is_err());
+    drop(store);
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn compacting_upgrades_a_legacy_store() {
+    let path = legacy_store("legacy-compact");
+    let mut store = loaded(&path, KvOptions::default());
+    store.compact().unwrap();
+    store.insert(b"d", b"5").unwrap();
+    store.close().unwrap();
+
+    assert!(fs::read(&path).unwrap().starts_with(b"ACTIONKV"));
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.get(b"a").unwrap(), Some(b"4".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), None);
+    assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(store.get(b"d").unwrap(), Some(b"5".to_vec()));
+}
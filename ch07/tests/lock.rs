@@ -0,0 +1,63 @@
+//! Locking is only implemented with `flock`, so none of this holds on Windows
+#![cfg(not(windows))]
+
+mod common;
+
+use ch07::actionkv::{ActionKv, KvError, KvOptions};
+use common::temp_store;
+
+fn read_only() -> KvOptions {
+    KvOptions {
+        read_only: true,
+        ..KvOptions::default()
+    }
+}
+
+fn segmented() -> KvOptions {
+    KvOptions {
+        segment_size: Some(1024),
+        ..KvOptions::default()
+    }
+}
+
+#[test]
+fn a_second_writer_is_locked_out() {
+    let path = temp_store("lock-writers");
+    let _writer = ActionKv::open(&path).unwrap();
+
+    match ActionKv::open(&path) {
+        Err(KvError::Locked { path: locked }) => assert_eq!(locked, path),
+        other => panic!("expected KvError::Locked, got {:?}", other.map(|_| ())),
+    }
+    assert!(matches!(
+        ActionKv::open_with(&path, read_only()),
+        Err(KvError::Locked { .. })
+    ));
+}
+
+#[test]
+fn readers_share_the_lock() {
+    let path = temp_store("lock-readers");
+    ActionKv::open(&path).unwrap();
+
+    let _first = ActionKv::open_with(&path, read_only()).unwrap();
+    let _second = ActionKv::open_with(&path, read_only()).unwrap();
+    assert!(matches!(ActionKv::open(&path), Err(KvError::Locked { .. })));
+}
+
+#[test]
+fn the_lock_is_released_on_drop() {
+    let path = temp_store("lock-drop");
+    drop(ActionKv::open(&path).unwrap());
+    ActionKv::open(&path).unwrap();
+}
+
+#[test]
+fn segmented_stores_are_locked_too() {
+    let path = temp_store("lock-segments");
+    let _writer = ActionKv::open_with(&path, segmented()).unwrap();
+    assert!(matches!(
+        ActionKv::open_with(&path, segmented()),
+        Err(KvError::Locked { .. })
+    ));
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use ch07::actionkv::{ActionKv, Compression, KvOptions};
+use common::model::{self, Model};
+use common::temp_store;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const SEEDS: u64 = 8;
+const OPS: usize = 300;
+
+/// Applies random operations to a store and a model, compacting and reopening
+/// the store now and then, and checks that they always agree
+fn run(name: &str, options: KvOptions) {
+    for seed in 0..SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let path = temp_store(&format!("{}-{}", name, seed));
+        let mut store = ActionKv::open_with(&path, options.clone()).unwrap();
+        store.load().unwrap();
+        let mut model = Model::new();
+
+        for i in 0..OPS {
+            let op = model::random_op(&mut rng);
+            model::apply(&mut store, &mut model, &op);
+
+            match rng.gen_range(0..40) {
+                0 => store.compact().unwrap(),
+                1 => {
+                    // Reopening after close reads the index from the hint file
+                    store.close().unwrap();
+                    store = ActionKv::open_with(&path, options.clone()).unwrap();
+                    store.load().unwrap();
+                }
+                2 => {
+                    // Dropping the store without closing it is a crash
+                    // right after the last write
+                    drop(store);
+                    store = ActionKv::open_with(&path, options.clone()).unwrap();
+                    store.load().unwrap();
+                }
+                _ => {}
+            }
+            let context = format!("seed {}, after op {} ({:?})", seed, i, op);
+            model::assert_matches(&store, &model, &context);
+        }
+
+        store.close().unwrap();
+        let mut store = ActionKv::open_with(&path, options.clone()).unwrap();
+        store.load().unwrap();
+        model::assert_matches(&store, &model, &format!("seed {}, reopened", seed));
+    }
+}
+
+#[test]
+fn random_operations_match_a_hashmap() {
+    run("model", KvOptions::default());
+}
+
+#[test]
+fn random_operations_match_a_hashmap_with_compression() {
+    run(
+        "model-lz4",
+        KvOptions {
+            compression: Compression::Lz4,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn random_operations_match_a_hashmap_in_segments_with_bloom_filters() {
+    run(
+        "model-segments",
+        KvOptions {
+            segment_size: Some(512),
+            compact_segments: 3,
+            bloom_filter: Some(0.01),
+            ..Default::default()
+        },
+    );
+}
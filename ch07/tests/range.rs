@@ -0,0 +1,128 @@
+mod common;
+
+use std::{
+    ops::Bound::{self, Excluded, Included, Unbounded},
+    thread,
+    time::Duration,
+};
+
+use ch07::actionkv::{ActionKv, Iter, KvOptions};
+use common::{loaded, temp_store};
+
+/// The keys an iterator yields, in order
+fn keys(iter: Iter<'_>) -> Vec<Vec<u8>> {
+    iter.map(|kv| kv.unwrap().key).collect()
+}
+
+/// A store with keys written out of order, and some binary ones
+fn store(name: &str) -> ActionKv {
+    let mut store = loaded(&temp_store(name), KvOptions::default());
+    for key in [
+        &b"b"[..],
+        b"d",
+        b"a",
+        b"c",
+        b"ab",
+        b"\xff",
+        b"\xff\xff",
+        b"\xff\xff\x00",
+        b"\xfe\xff",
+    ] {
+        store.insert(key, key).unwrap();
+    }
+    store
+}
+
+#[test]
+fn range_is_in_key_byte_order() {
+    let store = store("range-order");
+    let all = keys(store.iter());
+    let mut sorted = all.clone();
+    sorted.sort();
+    assert_eq!(all, sorted);
+    assert_eq!(all.len(), 9);
+
+    for kv in store.iter() {
+        let kv = kv.unwrap();
+        assert_eq!(kv.key, kv.value);
+    }
+}
+
+#[test]
+fn range_bounds() {
+    let store = store("range-bounds");
+    let range = |start: Bound<&[u8]>, end: Bound<&[u8]>| keys(store.range((start, end)));
+    assert_eq!(
+        range(Included(b"ab"), Excluded(b"c")),
+        [b"ab".to_vec(), b"b".to_vec()]
+    );
+    assert_eq!(
+        range(Included(b"ab"), Included(b"c")),
+        [b"ab".to_vec(), b"b".to_vec(), b"c".to_vec()]
+    );
+    assert_eq!(
+        range(Excluded(b"a"), Included(b"b")),
+        [b"ab".to_vec(), b"b".to_vec()]
+    );
+    assert_eq!(range(Unbounded, Excluded(b"ab")), [b"a".to_vec()]);
+    assert_eq!(
+        range(Included(b"\xff\xff"), Unbounded),
+        [b"\xff\xff".to_vec(), b"\xff\xff\x00".to_vec()]
+    );
+    assert!(range(Included(b"x"), Excluded(b"y")).is_empty());
+}
+
+#[test]
+fn scan_prefix_stops_after_the_prefix() {
+    let store = store("range-prefix");
+    assert_eq!(
+        keys(store.scan_prefix(b"a")),
+        [b"a".to_vec(), b"ab".to_vec()]
+    );
+    assert_eq!(keys(store.scan_prefix(b"ab")), [b"ab".to_vec()]);
+    assert!(keys(store.scan_prefix(b"abc")).is_empty());
+    assert_eq!(keys(store.scan_prefix(b"")).len(), 9);
+}
+
+#[test]
+fn scan_prefix_of_0xff_bytes() {
+    let store = store("range-prefix-ff");
+    assert_eq!(
+        keys(store.scan_prefix(b"\xff")),
+        [
+            b"\xff".to_vec(),
+            b"\xff\xff".to_vec(),
+            b"\xff\xff\x00".to_vec()
+        ]
+    );
+    assert_eq!(
+        keys(store.scan_prefix(b"\xff\xff")),
+        [b"\xff\xff".to_vec(), b"\xff\xff\x00".to_vec()]
+    );
+    assert_eq!(keys(store.scan_prefix(b"\xfe")), [b"\xfe\xff".to_vec()]);
+}
+
+#[test]
+fn deleted_and_expired_keys_are_skipped() {
+    let mut store = store("range-deleted");
+    store.delete(b"ab").unwrap();
+    store.delete(b"\xff\xff").unwrap();
+    store
+        .insert_with_ttl(b"aa", b"aa", Duration::from_millis(50))
+        .unwrap();
+    store
+        .insert_with_ttl(b"c", b"c", Duration::from_millis(50))
+        .unwrap();
+    assert_eq!(keys(store.scan_prefix(b"a")).len(), 2);
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(keys(store.scan_prefix(b"a")), [b"a".to_vec()]);
+    assert_eq!(
+        keys(store.range((Included(&b"a"[..]), Included(&b"d"[..])))),
+        [b"a".to_vec(), b"b".to_vec(), b"d".to_vec()]
+    );
+    assert_eq!(
+        keys(store.scan_prefix(b"\xff")),
+        [b"\xff".to_vec(), b"\xff\xff\x00".to_vec()]
+    );
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::{net::TcpListener, thread};
+
+use ch07::actionkv::{
+    net::{self, KvClient, Request, Response},
+    ActionKv, SharedKv,
+};
+use common::temp_store;
+
+/// Starts a server for a fresh store on a free localhost port
+fn start_server(name: &str) -> std::net::SocketAddr {
+    let mut store = ActionKv::open(&temp_store(name)).unwrap();
+    store.load().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || net::serve(listener, SharedKv::new(store)));
+    addr
+}
+
+#[test]
+fn client_round_trip() {
+    let addr = start_server("round-trip");
+    let mut client = KvClient::connect(addr).unwrap();
+
+    assert_eq!(client.get(b"missing").unwrap(), None);
+
+    client.insert(b"user:1", b"alice").unwrap();
+    client.insert(b"user:2", b"bob").unwrap();
+    client.insert(b"group:1", b"admins").unwrap();
+    client.update(b"user:2", b"robert").unwrap();
+    assert_eq!(client.get(b"user:2").unwrap(), Some(b"robert".to_vec()));
+
+    client.delete(b"user:1").unwrap();
+    assert_eq!(client.get(b"user:1").unwrap(), None);
+
+    let keys: Vec<Vec<u8>> = client
+        .list(b"")
+        .unwrap()
+        .into_iter()
+        .map(|kv| kv.key)
+        .collect();
+    assert_eq!(keys, vec![b"group:1".to_vec(), b"user:2".to_vec()]);
+    assert_eq!(client.list(b"user:").unwrap().len(), 1);
+}
+
+#[test]
+fn clients_share_one_store() {
+    let addr = start_server("shared");
+
+    let writers: Vec<_> = (0..4u8)
+        .map(|id| {
+            thread::spawn(move || {
+                let mut client = KvClient::connect(addr).unwrap();
+                for i in 0..25u8 {
+                    client.insert(&[id, i], &[i]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let mut client = KvClient::connect(addr).unwrap();
+    assert_eq!(client.list(b"").unwrap().len(), 100);
+    assert_eq!(client.get(&[3, 7]).unwrap(), Some(vec![7]));
+}
+
+#[test]
+fn binary_keys_and_values() {
+    let addr = start_server("binary");
+    let mut client = KvClient::connect(addr).unwrap();
+
+    let key = [0u8, 255, 10, 13];
+    let value = vec![0u8; 100_000];
+    assert_eq!(
+        client
+            .request(&Request::Insert(key.to_vec(), value.clone()))
+            .unwrap(),
+        Response::Ok
+    );
+    assert_eq!(client.get(&key).unwrap(), Some(value));
+}
+
+#[test]
+fn responses_too_large_for_a_frame_are_errors() {
+    let mut store = ActionKv::open(&temp_store("too-large")).unwrap();
+    store.load().unwrap();
+    let value = vec![7u8; 40 * 1024 * 1024];
+    store.insert(b"big:1", &value).unwrap();
+    store.insert(b"big:2", &value).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || net::serve(listener, SharedKv::new(store)));
+
+    let mut client = KvClient::connect(addr).unwrap();
+    let response = client.request(&Request::List(b"big:".to_vec())).unwrap();
+    assert!(
+        matches!(&response, Response::Error(message) if message.contains("larger than the limit")),
+        "expected an error response"
+    );
+
+    // The connection is still usable
+    assert_eq!(client.list(b"big:1").unwrap().len(), 1);
+}
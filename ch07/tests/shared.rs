@@ -0,0 +1,70 @@
+mod common;
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use ch07::actionkv::{ActionKv, SharedKv, WriteBatch};
+use common::temp_store;
+
+const WRITES: u32 = 500;
+const READERS: usize = 4;
+
+fn number(value: Option<Vec<u8>>) -> Option<u32> {
+    value.map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[test]
+fn readers_see_consistent_values_during_writes() {
+    let path = temp_store("shared-readers");
+    let mut store = ActionKv::open(&path).unwrap();
+    store.load().unwrap();
+    let shared = SharedKv::new(store);
+    let writing = AtomicBool::new(true);
+
+    thread::scope(|scope| {
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let shared = shared.clone();
+                let writing = &writing;
+                scope.spawn(move || {
+                    let mut last = None;
+                    let mut reads = 0;
+                    while writing.load(Ordering::Acquire) || reads == 0 {
+                        // A counter that only goes up is never seen going down
+                        let counter = number(shared.get(b"counter").unwrap());
+                        assert!(counter >= last, "{:?} after {:?}", counter, last);
+                        last = counter;
+
+                        // Both keys of a batch are seen written, or neither
+                        let store = shared.read();
+                        let a = number(store.get(b"a").unwrap());
+                        let b = number(store.get(b"b").unwrap());
+                        assert_eq!(a, b);
+                        reads += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..WRITES {
+            shared.insert(b"counter", &i.to_le_bytes()).unwrap();
+            let mut batch = WriteBatch::new();
+            batch
+                .put(b"a", &i.to_le_bytes())
+                .put(b"b", &i.to_le_bytes());
+            shared.write_batch(batch).unwrap();
+        }
+        writing.store(false, Ordering::Release);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    });
+
+    let last = Some(WRITES - 1);
+    assert_eq!(number(shared.get(b"counter").unwrap()), last);
+    assert_eq!(number(shared.get(b"a").unwrap()), last);
+    assert_eq!(number(shared.get(b"b").unwrap()), last);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use ch07::actionkv::{KvError, KvOptions, Position, WriteBatch};
+use common::{loaded, temp_store};
+
+#[test]
+fn snapshots_keep_old_values() {
+    let path = temp_store("snapshot-old-values");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"2").unwrap();
+    let snapshot = store.snapshot().unwrap();
+
+    store.insert(b"a", b"changed").unwrap();
+    store.delete(b"b").unwrap();
+    store.insert(b"c", b"3").unwrap();
+
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(snapshot.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(snapshot.get(b"c").unwrap(), None);
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(store.get(b"a").unwrap(), Some(b"changed".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), None);
+
+    // Rebuilding the view from the log gives the same result
+    let rebuilt = store.snapshot_at(snapshot.end()).unwrap();
+    let pairs = |iter: ch07::actionkv::Iter<'_>| -> Vec<_> {
+        iter.map(|kv| {
+            let kv = kv.unwrap();
+            (kv.key, kv.value)
+        })
+        .collect()
+    };
+    assert_eq!(pairs(rebuilt.iter()), pairs(snapshot.iter()));
+}
+
+#[cfg(unix)]
+#[test]
+fn snapshots_survive_compaction() {
+    let path = temp_store("snapshot-compaction");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"a", b"1").unwrap();
+    let snapshot = store.snapshot().unwrap();
+    store.insert(b"a", b"2").unwrap();
+    store.compact().unwrap();
+
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn history_lists_every_version_in_order() {
+    for (name, options) in [
+        ("snapshot-history", KvOptions::default()),
+        (
+            "snapshot-history-segments",
+            KvOptions {
+                segment_size: Some(64),
+                ..KvOptions::default()
+            },
+        ),
+    ] {
+        let path = temp_store(name);
+        let mut store = loaded(&path, options);
+        store.insert(b"k", b"v1").unwrap();
+        store.insert(b"other", b"x").unwrap();
+        store.insert(b"k", b"v2").unwrap();
+        store.delete(b"k").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"k", b"v3").put(b"other", b"y");
+        store.write_batch(batch).unwrap();
+
+        let history = store.history(b"k").unwrap();
+        let values: Vec<_> = history.iter().map(|entry| entry.value.clone()).collect();
+        assert_eq!(
+            values,
+            [
+                Some(b"v1".to_vec()),
+                Some(b"v2".to_vec()),
+                None,
+                Some(b"v3".to_vec())
+            ],
+            "{}",
+            name
+        );
+        assert!(history
+            .windows(2)
+            .all(|pair| pair[0].position < pair[1].position));
+        assert_eq!(store.history(b"missing").unwrap(), []);
+    }
+}
+
+#[test]
+fn snapshots_at_positions_not_in_the_log_fail() {
+    let path = temp_store("snapshot-gone");
+    let options = KvOptions {
+        segment_size: Some(64),
+        ..KvOptions::default()
+    };
+    let mut store = loaded(&path, options);
+    for i in 0..10u8 {
+        store.insert(b"k", &[i; 16]).unwrap();
+    }
+    let early = store.history(b"k").unwrap()[0].position;
+    assert_eq!(early.segment, 0);
+    store.snapshot_at(early).unwrap();
+
+    store.compact().unwrap();
+    assert!(!store.segments().contains(&0));
+    assert!(matches!(
+        store.snapshot_at(early),
+        Err(KvError::NoSuchPosition { position }) if position == early
+    ));
+
+    let active = *store.segments().last().unwrap();
+    for offset in [0, 1 << 20] {
+        let position = Position {
+            segment: active,
+            offset,
+        };
+        assert!(matches!(
+            store.snapshot_at(position),
+            Err(KvError::NoSuchPosition { .. })
+        ));
+    }
+}
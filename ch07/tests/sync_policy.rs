@@ -0,0 +1,147 @@
+//! Reading the file back can't tell whether a write reached the disk or is
+//! still in the page cache, so these count the `fsync` calls made instead.
+
+mod common;
+
+use std::time::Duration;
+
+use ch07::actionkv::{ActionKv, KvOptions, SyncPolicy, WriteBatch};
+use common::temp_store;
+
+fn open(name: &str, sync: SyncPolicy) -> (ActionKv, std::path::PathBuf) {
+    let path = temp_store(name);
+    let store = ActionKv::open_with(
+        &path,
+        KvOptions {
+            sync,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    (store, path)
+}
+
+#[test]
+fn never_only_syncs_on_request() {
+    let (mut store, _) = open("never", SyncPolicy::Never);
+
+    store.insert(b"k1", b"value-1").unwrap();
+    store.insert(b"k2", b"value-2").unwrap();
+    assert_eq!(store.unsynced_writes(), 2);
+    assert_eq!(store.syncs(), 0);
+
+    store.sync().unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 1);
+}
+
+#[test]
+fn every_write_syncs_before_returning() {
+    let (mut store, _) = open("every-write", SyncPolicy::EveryWrite);
+
+    store.insert(b"k1", b"value-1").unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 1);
+
+    store.delete(b"k1").unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 2);
+}
+
+#[test]
+fn every_n_syncs_on_the_nth_write() {
+    let (mut store, _) = open("every-n", SyncPolicy::EveryN(3));
+
+    store.insert(b"k1", b"value-1").unwrap();
+    store.insert(b"k2", b"value-2").unwrap();
+    assert_eq!(store.unsynced_writes(), 2);
+    assert_eq!(store.syncs(), 0);
+
+    store.insert(b"k3", b"value-3").unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 1);
+
+    store.insert(b"k4", b"value-4").unwrap();
+    assert_eq!(store.unsynced_writes(), 1);
+    assert_eq!(store.syncs(), 1);
+}
+
+#[test]
+fn interval_syncs_once_elapsed() {
+    let (mut store, _) = open(
+        "interval-long",
+        SyncPolicy::Interval(Duration::from_secs(3600)),
+    );
+    store.insert(b"k1", b"value-1").unwrap();
+    assert_eq!(store.unsynced_writes(), 1);
+    assert_eq!(store.syncs(), 0);
+
+    let (mut store, _) = open(
+        "interval-short",
+        SyncPolicy::Interval(Duration::from_millis(10)),
+    );
+    store.insert(b"k1", b"value-1").unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    store.insert(b"k2", b"value-2").unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 1);
+}
+
+#[test]
+fn interval_syncs_when_due_without_a_write() {
+    let (mut store, _) = open(
+        "interval-idle",
+        SyncPolicy::Interval(Duration::from_millis(10)),
+    );
+    store.sync_if_due().unwrap();
+    assert_eq!(store.syncs(), 0);
+
+    store.insert(b"k1", b"value-1").unwrap();
+    store.sync_if_due().unwrap();
+    assert_eq!(store.unsynced_writes(), 1);
+
+    std::thread::sleep(Duration::from_millis(20));
+    store.sync_if_due().unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 1);
+
+    // Nothing left to sync
+    std::thread::sleep(Duration::from_millis(20));
+    store.sync_if_due().unwrap();
+    assert_eq!(store.syncs(), 1);
+}
+
+#[test]
+fn never_is_not_synced_when_due() {
+    let (mut store, _) = open("never-due", SyncPolicy::Never);
+    store.insert(b"k1", b"value-1").unwrap();
+    store.sync_if_due().unwrap();
+    assert_eq!(store.unsynced_writes(), 1);
+    assert_eq!(store.syncs(), 0);
+}
+
+#[test]
+fn batch_counts_as_one_write() {
+    let (mut store, _) = open("batch", SyncPolicy::EveryN(2));
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"k1", b"value-1").put(b"k2", b"value-2");
+    store.write_batch(batch).unwrap();
+    assert_eq!(store.unsynced_writes(), 1);
+    assert_eq!(store.syncs(), 0);
+
+    store.insert(b"k3", b"value-3").unwrap();
+    assert_eq!(store.unsynced_writes(), 0);
+    assert_eq!(store.syncs(), 1);
+}
+
+#[test]
+fn synced_writes_survive_reopening() {
+    let (mut store, path) = open("reopen", SyncPolicy::EveryWrite);
+    store.insert(b"k1", b"value-1").unwrap();
+    drop(store);
+
+    let mut store = ActionKv::open(&path).unwrap();
+    store.load().unwrap();
+    assert_eq!(store.get(b"k1").unwrap(), Some(b"value-1".to_vec()));
+}
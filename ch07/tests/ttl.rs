@@ -0,0 +1,85 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use ch07::actionkv::{Change, KvOptions, Position, Tail};
+use common::{loaded, temp_store};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+#[test]
+fn keys_expire() {
+    let path = temp_store("ttl-expire");
+    let mut store = loaded(&path, KvOptions::default());
+    store
+        .insert_with_ttl(b"short", b"1", Duration::from_millis(50))
+        .unwrap();
+    store.insert_with_ttl(b"long", b"2", HOUR).unwrap();
+    assert_eq!(store.get(b"short").unwrap(), Some(b"1".to_vec()));
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(store.get(b"short").unwrap(), None);
+    assert_eq!(store.get(b"long").unwrap(), Some(b"2".to_vec()));
+    let keys: Vec<_> = store.iter().map(|kv| kv.unwrap().key).collect();
+    assert_eq!(keys, [b"long".to_vec()]);
+}
+
+#[test]
+fn huge_ttls_never_expire() {
+    let path = temp_store("ttl-huge");
+    let mut store = loaded(&path, KvOptions::default());
+    // Just over 2^64 milliseconds, which would wrap around to 384 if truncated
+    let ttl = Duration::from_secs(18_446_744_073_709_552);
+    store.insert_with_ttl(b"k", b"v", ttl).unwrap();
+    store.insert_with_ttl(b"max", b"v", Duration::MAX).unwrap();
+
+    assert_eq!(store.index[b"k".as_slice()].expires_at, Some(u64::MAX));
+    assert_eq!(store.index[b"max".as_slice()].expires_at, Some(u64::MAX));
+}
+
+#[test]
+fn expiry_survives_reloading() {
+    let path = temp_store("ttl-reload");
+    let mut store = loaded(&path, KvOptions::default());
+    store
+        .insert_with_ttl(b"short", b"1", Duration::from_millis(50))
+        .unwrap();
+    store.insert_with_ttl(b"long", b"2", HOUR).unwrap();
+    let expires_at = store.index[b"long".as_slice()].expires_at;
+    store.close().unwrap();
+
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.index[b"long".as_slice()].expires_at, expires_at);
+    drop(store);
+
+    thread::sleep(Duration::from_millis(100));
+    let store = loaded(&path, KvOptions::default());
+    assert_eq!(store.get(b"short").unwrap(), None);
+    assert!(!store.index.contains_key(b"short".as_slice()));
+    assert_eq!(store.get(b"long").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn compaction_drops_expired_records() {
+    let path = temp_store("ttl-compact");
+    let mut store = loaded(&path, KvOptions::default());
+    store
+        .insert_with_ttl(b"short", b"1", Duration::from_millis(10))
+        .unwrap();
+    store.insert_with_ttl(b"long", b"2", HOUR).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    store.compact().unwrap();
+
+    // Only the live record is left in the file
+    let start = Position {
+        segment: 0,
+        offset: 0,
+    };
+    let mut tail = Tail::open(&path, start).unwrap();
+    let mut keys = Vec::new();
+    while let Some(Change { key, .. }) = tail.next_change().unwrap() {
+        keys.push(key);
+    }
+    assert_eq!(keys, [b"long".to_vec()]);
+    assert_eq!(store.get(b"long").unwrap(), Some(b"2".to_vec()));
+}
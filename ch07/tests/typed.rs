@@ -0,0 +1,94 @@
+mod common;
+
+use ch07::actionkv::{ActionKv, Codec, KvError, TypedKv};
+use common::temp_store;
+use serde::{Deserialize, Serialize};
+
+const CODECS: [Codec; 3] = [Codec::Json, Codec::Bincode, Codec::Cbor];
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Account {
+    owner: String,
+    balance: i64,
+}
+
+fn account() -> Account {
+    Account {
+        owner: "ferris".to_string(),
+        balance: -12,
+    }
+}
+
+#[test]
+fn values_read_back_with_every_codec() {
+    for codec in CODECS {
+        let path = temp_store(&format!("typed-{:?}", codec));
+        let mut store = TypedKv::<u32, Account>::open(&path, codec).unwrap();
+        store.load().unwrap();
+        store.insert(&7, &account()).unwrap();
+        store.close().unwrap();
+
+        let mut store = TypedKv::<u32, Account>::open(&path, codec).unwrap();
+        store.load().unwrap();
+        assert_eq!(store.get(&7).unwrap(), Some(account()));
+        assert_eq!(store.get(&8).unwrap(), None);
+    }
+}
+
+#[test]
+fn opening_with_another_codec_fails() {
+    let path = temp_store("typed-mismatch");
+    let mut store = TypedKv::<u32, Account>::open(&path, Codec::Json).unwrap();
+    store.insert(&7, &account()).unwrap();
+    store.close().unwrap();
+
+    match TypedKv::<u32, Account>::open(&path, Codec::Bincode) {
+        Err(KvError::CodecMismatch { expected, found }) => {
+            assert_eq!(expected, Some(Codec::Bincode));
+            assert_eq!(found, Some(Codec::Json));
+        }
+        other => panic!("expected a codec mismatch, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn raw_stores_cant_be_opened_as_typed() {
+    let path = temp_store("typed-raw");
+    let mut store = ActionKv::open(&path).unwrap();
+    store.insert(b"k", b"v").unwrap();
+    store.close().unwrap();
+
+    assert!(matches!(
+        TypedKv::<String, String>::open(&path, Codec::Json),
+        Err(KvError::CodecMismatch { found: None, .. })
+    ));
+}
+
+#[test]
+fn decoding_with_another_codec_fails() {
+    for written_with in CODECS {
+        let bytes = written_with.encode(&account()).unwrap();
+        for read_with in CODECS.into_iter().filter(|&codec| codec != written_with) {
+            let decoded = read_with.decode::<Account>(&bytes);
+            assert!(
+                matches!(decoded, Err(KvError::Codec(_))),
+                "{:?} read as {:?}: {:?}",
+                written_with,
+                read_with,
+                decoded
+            );
+        }
+    }
+}
+
+#[test]
+fn reading_another_type_fails() {
+    let path = temp_store("typed-type");
+    let mut store = TypedKv::<u32, Account>::open(&path, Codec::Json).unwrap();
+    store.insert(&7, &account()).unwrap();
+    store.close().unwrap();
+
+    let mut store = TypedKv::<u32, Vec<u8>>::open(&path, Codec::Json).unwrap();
+    store.load().unwrap();
+    assert!(matches!(store.get(&7), Err(KvError::Codec(_))));
+}
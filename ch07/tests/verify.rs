@@ -0,0 +1,114 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use ch07::actionkv::{ActionKv, KvError, KvOptions, VerifyReport};
+use common::{loaded, temp_store};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+/// A store of three records, with the offset of each
+fn three_records(name: &str) -> (std::path::PathBuf, Vec<u64>) {
+    let path = temp_store(name);
+    let mut store = loaded(&path, KvOptions::default());
+    let mut offsets = Vec::new();
+    for key in [b"k1", b"k2", b"k3"] {
+        store.insert(key, b"some value").unwrap();
+        offsets.push(store.history(key).unwrap()[0].position.offset);
+    }
+    store.close().unwrap();
+    (path, offsets)
+}
+
+fn verify(path: &Path) -> VerifyReport {
+    ActionKv::open(path).unwrap().verify().unwrap()
+}
+
+fn damage(path: &Path, offset: u64, f: impl FnOnce(&mut u8)) {
+    let mut data = fs::read(path).unwrap();
+    f(&mut data[offset as usize]);
+    fs::write(path, data).unwrap();
+}
+
+#[test]
+fn clean_files_have_no_errors() {
+    let (path, _) = three_records("verify-clean");
+    let report = verify(&path);
+    assert_eq!(report.records, 3);
+    assert!(report.errors.is_empty());
+}
+
+#[test]
+fn flipped_bytes_are_reported_and_skipped() {
+    let (path, offsets) = three_records("verify-flipped");
+    // In the value of the second record
+    damage(&path, offsets[1] + 16, |byte| *byte ^= 0x01);
+
+    let report = verify(&path);
+    assert_eq!(report.records, 3);
+    match report.errors.as_slice() {
+        [(0, KvError::Corruption { offset, .. })] => assert_eq!(*offset, offsets[1]),
+        errors => panic!("unexpected errors: {:?}", errors),
+    }
+}
+
+#[test]
+fn torn_tails_are_reported() {
+    let (path, offsets) = three_records("verify-torn");
+    let len = fs::metadata(&path).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 3)
+        .unwrap();
+
+    let report = verify(&path);
+    assert_eq!(report.records, 3);
+    match report.errors.as_slice() {
+        [(0, KvError::TornRecord { offset })] => assert_eq!(*offset, offsets[2]),
+        errors => panic!("unexpected errors: {:?}", errors),
+    }
+}
+
+#[test]
+fn damaged_lengths_resume_at_the_next_record() {
+    let (path, offsets) = three_records("verify-length");
+    // The high byte of the second record's value length
+    damage(&path, offsets[1] + 12, |byte| *byte = 0x7F);
+
+    let report = verify(&path);
+    assert_eq!(report.records, 3);
+    assert!(matches!(
+        report.errors.as_slice(),
+        [(0, KvError::TornRecord { .. })]
+    ));
+}
+
+#[test]
+fn long_damaged_stretches_are_skipped() {
+    let path = temp_store("verify-garbage");
+    let mut store = loaded(&path, KvOptions::default());
+    store.insert(b"k1", b"before").unwrap();
+    store.close().unwrap();
+
+    // A record whose length runs past the end, then garbage spanning several
+    // windows, then an intact record
+    let mut data = fs::read(&path).unwrap();
+    let first = data.len();
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 2, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0x7F]);
+    let mut garbage = vec![0; 1 << 20];
+    StdRng::seed_from_u64(7).fill_bytes(&mut garbage);
+    data.extend_from_slice(&garbage);
+    fs::write(&path, &data).unwrap();
+
+    let mut store = ActionKv::open(&path).unwrap();
+    store.insert(b"k2", b"after").unwrap();
+    drop(store);
+
+    let report = verify(&path);
+    assert_eq!(report.records, 3);
+    match report.errors.as_slice() {
+        [(0, KvError::TornRecord { offset })] => assert_eq!(*offset, first as u64),
+        errors => panic!("unexpected errors: {:?}", errors),
+    }
+}